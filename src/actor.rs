@@ -56,6 +56,11 @@ impl<T> MailBox<T> {
 
         Ok(())
     }
+
+    /// depth returns the number of messages waiting to be handled by the actor
+    pub fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
 }
 
 impl<T> std::clone::Clone for MailBox<T> {
@@ -0,0 +1,356 @@
+mod mqtt;
+mod nats;
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::{select, sync::mpsc};
+
+use crate::woduler::{
+    event::Bus,
+    process::{mail, Mail},
+};
+
+const BUFFER_SIZE: usize = 64;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// WILDCARDS are the wildcards of MQTT topic filters and NATS subjects
+const WILDCARDS: [char; 4] = ['+', '#', '*', '>'];
+
+/// Message is a payload paired with the topic it belongs to
+type Message = (String, Vec<u8>);
+
+pub struct Config {
+    /// url of the broker, the scheme selects the protocol - "mqtt://" or "nats://"
+    pub url: String,
+    /// exports are the bus topics which are mirrored to the broker
+    pub exports: Vec<Mapping>,
+    /// imports are the broker topics which are mirrored to the bus
+    pub imports: Vec<Mapping>,
+    /// qos is the MQTT quality of service level used for publishing and subscribing,
+    /// NATS only supports at most once delivery hence it is ignored for NATS
+    pub qos: u8,
+}
+
+/// Mapping pairs a topic on the bus with a topic on the broker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub bus: String,
+    pub broker: String,
+}
+
+impl Mapping {
+    /// parse_list takes in a comma separated list of `bus_topic=>broker_topic` pairs
+    /// and returns the mappings represented by it
+    ///
+    /// Broker topics with wildcards are rejected, the messages are mapped to the bus by
+    /// the exact topic they are received on and a topic with wildcards cannot be
+    /// published to
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once("=>") {
+                Some((_, broker)) if broker.contains(WILDCARDS) => Err(anyhow!(
+                    "invalid bridge mapping \"{}\" - broker topics cannot contain wildcards",
+                    entry
+                )),
+                Some((bus, broker)) if !bus.trim().is_empty() && !broker.trim().is_empty() => {
+                    Ok(Self {
+                        bus: bus.trim().to_string(),
+                        broker: broker.trim().to_string(),
+                    })
+                }
+                _ => Err(anyhow!(
+                    "invalid bridge mapping \"{}\" - expected \"bus_topic=>broker_topic\"",
+                    entry
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Connector creates connections to a broker
+#[async_trait::async_trait]
+pub trait Connector: Send + Sync {
+    /// connect connects to the broker and subscribes to the given broker topics
+    async fn connect(&self, subscriptions: &[String]) -> Result<Box<dyn Connection>>;
+}
+
+/// Connection is an established connection to a broker
+#[async_trait::async_trait]
+pub trait Connection: Send {
+    /// publish sends the payload to the broker topic
+    async fn publish(&mut self, topic: &str, payload: Vec<u8>) -> Result<()>;
+
+    /// next returns the next message received on one of the subscribed broker topics,
+    /// an error is returned once the connection is lost
+    ///
+    /// next MUST be cancel safe as it is raced against the outgoing messages
+    async fn next(&mut self) -> Result<Message>;
+}
+
+/// start connects the bus to the broker given in the config and keeps mirroring
+/// the topics in the background
+pub fn start(cfg: Config, bus: Bus) -> Result<()> {
+    let connector: Box<dyn Connector> = if let Some(addr) = cfg.url.strip_prefix("mqtt://") {
+        Box::new(mqtt::Connector::new(addr, cfg.qos)?)
+    } else if cfg.url.starts_with("nats://") {
+        if cfg.qos > 0 {
+            log::warn!("bridge: NATS supports only at most once delivery - ignoring qos");
+        }
+        Box::new(nats::Connector::new(&cfg.url))
+    } else {
+        return Err(anyhow!(
+            "unsupported bridge url \"{}\" - supported schemes: \"mqtt://\", \"nats://\"",
+            cfg.url
+        ));
+    };
+
+    log::info!("bridging event bus to {}", cfg.url);
+
+    tokio::spawn(run(connector, cfg.exports, cfg.imports, bus));
+
+    Ok(())
+}
+
+/// run mirrors the exported bus topics to the broker and the imported broker topics
+/// to the bus, reconnecting with an exponential backoff whenever the connection is lost
+async fn run(
+    connector: Box<dyn Connector>,
+    exports: Vec<Mapping>,
+    imports: Vec<Mapping>,
+    bus: Bus,
+) {
+    let mut outbound = export(exports, bus.clone()).await;
+    let inbound: HashMap<String, String> = imports.into_iter().map(|m| (m.broker, m.bus)).collect();
+    let subscriptions: Vec<String> = inbound.keys().cloned().collect();
+
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        match connector.connect(&subscriptions).await {
+            Ok(mut conn) => {
+                log::info!("bridge: connected to the broker");
+                backoff = MIN_BACKOFF;
+
+                if let Err(err) = relay(conn.as_mut(), &mut outbound, &inbound, bus.clone()).await {
+                    log::warn!("bridge: lost connection to the broker: {}", err);
+                }
+            }
+            Err(err) => {
+                log::warn!("bridge: failed to connect to the broker: {}", err);
+            }
+        }
+
+        // Messages cannot be held back while the broker is unreachable as that would
+        // stall the publishers on the bus, hence they are dropped
+        let mut dropped = 0u64;
+        let wait = tokio::time::sleep(backoff);
+        tokio::pin!(wait);
+        loop {
+            select! {
+                _ = &mut wait => break,
+                msg = outbound.recv() => match msg {
+                    Some(_) => dropped += 1,
+                    None => return,
+                },
+            }
+        }
+        if dropped > 0 {
+            log::warn!("bridge: dropped {} messages while disconnected", dropped);
+        }
+
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// export subscribes to the exported bus topics and returns a receiver of the messages
+/// on them paired with the broker topic they should be published to
+async fn export(exports: Vec<Mapping>, mut bus: Bus) -> mpsc::Receiver<Message> {
+    let (tx, rx) = mpsc::channel(BUFFER_SIZE);
+
+    for mapping in exports {
+        let (_, mut sub) = bus.subscribe(mapping.bus).await;
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            while let Some(mail) = sub.recv().await {
+                if tx.send((mapping.broker.clone(), mail.data)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    rx
+}
+
+/// relay moves messages between the bus and the broker till the connection fails
+async fn relay(
+    conn: &mut dyn Connection,
+    outbound: &mut mpsc::Receiver<Message>,
+    inbound: &HashMap<String, String>,
+    mut bus: Bus,
+) -> Result<()> {
+    loop {
+        select! {
+            msg = outbound.recv() => match msg {
+                Some((topic, payload)) => conn.publish(&topic, payload).await?,
+                None => return Ok(()),
+            },
+            msg = conn.next() => {
+                let (topic, payload) = msg?;
+
+                match inbound.get(&topic) {
+                    Some(bus_topic) => {
+                        let mail = Mail {
+                            typ: mail::data_type::DATA,
+                            size: payload.len() as u64,
+                            data: payload,
+                        };
+                        bus.publish(bus_topic, mail).await;
+                    }
+                    None => log::debug!("bridge: ignoring message on unmapped topic: {}", topic),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+
+    /// Broker is an in memory stand-in for a broker which fails the first connection attempt
+    #[derive(Clone, Default)]
+    struct Broker {
+        attempts: Arc<Mutex<u32>>,
+        published: Arc<Mutex<Vec<Message>>>,
+        incoming: Arc<Mutex<Option<mpsc::Receiver<Message>>>>,
+    }
+
+    struct BrokerConnection {
+        broker: Broker,
+        incoming: mpsc::Receiver<Message>,
+    }
+
+    #[async_trait::async_trait]
+    impl Connector for Broker {
+        async fn connect(&self, _: &[String]) -> Result<Box<dyn Connection>> {
+            let mut attempts = self.attempts.lock().await;
+            *attempts += 1;
+            if *attempts == 1 {
+                return Err(anyhow!("broker unavailable"));
+            }
+
+            let incoming = self.incoming.lock().await.take().unwrap();
+            Ok(Box::new(BrokerConnection {
+                broker: self.clone(),
+                incoming,
+            }))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Connection for BrokerConnection {
+        async fn publish(&mut self, topic: &str, payload: Vec<u8>) -> Result<()> {
+            self.broker
+                .published
+                .lock()
+                .await
+                .push((topic.to_string(), payload));
+            Ok(())
+        }
+
+        async fn next(&mut self) -> Result<Message> {
+            self.incoming
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("connection closed"))
+        }
+    }
+
+    #[test]
+    fn test_parse_mappings() {
+        assert_eq!(
+            Mapping::parse_list("app=a.data=>telemetry/a, b.data => b").unwrap(),
+            vec![
+                Mapping {
+                    bus: "app=a.data".to_string(),
+                    broker: "telemetry/a".to_string()
+                },
+                Mapping {
+                    bus: "b.data".to_string(),
+                    broker: "b".to_string()
+                }
+            ]
+        );
+        assert!(Mapping::parse_list("").unwrap().is_empty());
+        assert!(Mapping::parse_list("app=a.data").is_err());
+        assert!(Mapping::parse_list("=>b").is_err());
+        for wildcard in [
+            "a=>telemetry/+",
+            "a=>telemetry/#",
+            "a=>telemetry.*",
+            "a=>telemetry.>",
+        ] {
+            assert!(Mapping::parse_list(wildcard).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge() {
+        let mut bus = Bus::new();
+        let broker = Broker::default();
+        let (incoming, rx) = mpsc::channel(8);
+        *broker.incoming.lock().await = Some(rx);
+
+        let (_, mut imported) = bus.subscribe("imported".to_string()).await;
+
+        tokio::spawn(run(
+            Box::new(broker.clone()),
+            vec![Mapping {
+                bus: "exported".to_string(),
+                broker: "out".to_string(),
+            }],
+            vec![Mapping {
+                bus: "imported".to_string(),
+                broker: "in".to_string(),
+            }],
+            bus.clone(),
+        ));
+
+        // The message is only imported once the bridge reconnects after the first
+        // failed attempt
+        incoming
+            .send(("in".to_string(), b"ping".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(imported.recv().await.unwrap().data, b"ping".to_vec());
+        assert_eq!(*broker.attempts.lock().await, 2);
+
+        let mail = Mail {
+            typ: mail::data_type::DATA,
+            size: 4,
+            data: b"pong".to_vec(),
+        };
+        bus.publish("exported", mail).await;
+        let published = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let published = broker.published.lock().await.clone();
+                if !published.is_empty() {
+                    return published;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("message was not exported in time");
+        assert_eq!(published, vec![("out".to_string(), b"pong".to_vec())]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use tokio::{select, sync::mpsc};
+use uuid::Uuid;
+
+use super::{Message, BUFFER_SIZE};
+
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+pub struct Connector {
+    host: String,
+    port: u16,
+    qos: QoS,
+}
+
+impl Connector {
+    /// new takes in the `host[:port]` of the broker and the quality of service level
+    pub fn new(addr: &str, qos: u8) -> Result<Self> {
+        let (host, port) = match addr.trim_end_matches('/').rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse()?),
+            None => (addr.trim_end_matches('/').to_string(), DEFAULT_PORT),
+        };
+
+        let qos = match qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => return Err(anyhow!("invalid MQTT qos: {} - expected 0, 1 or 2", qos)),
+        };
+
+        Ok(Self { host, port, qos })
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Connector for Connector {
+    async fn connect(&self, subscriptions: &[String]) -> Result<Box<dyn super::Connection>> {
+        let client_id = format!("hyperion-{}", Uuid::new_v4());
+        let mut options = MqttOptions::new(client_id, self.host.clone(), self.port);
+        options.set_keep_alive(KEEP_ALIVE);
+
+        let (client, mut eventloop) = AsyncClient::new(options, BUFFER_SIZE);
+
+        // Wait for the broker to accept the connection
+        loop {
+            if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await? {
+                break;
+            }
+        }
+
+        let (tx, rx) = mpsc::channel(BUFFER_SIZE);
+        tokio::spawn(Self::poll(eventloop, tx));
+
+        // The session is clean hence the subscriptions are made on every connect, the
+        // requests are queued for the event loop which is being polled by now so that
+        // more subscriptions than fit in the queue do not block
+        for topic in subscriptions {
+            client.subscribe(topic.clone(), self.qos).await?;
+        }
+
+        Ok(Box::new(Connection {
+            client,
+            qos: self.qos,
+            incoming: rx,
+        }))
+    }
+}
+
+impl Connector {
+    /// poll drives the event loop of the connection and forwards the received messages
+    /// till the connection fails or is dropped
+    async fn poll(mut eventloop: EventLoop, tx: mpsc::Sender<Result<Message>>) {
+        loop {
+            let event = select! {
+                event = eventloop.poll() => event,
+                _ = tx.closed() => return,
+            };
+
+            let msg = match event {
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    Ok((publish.topic, publish.payload.to_vec()))
+                }
+                Ok(_) => continue,
+                Err(err) => Err(anyhow!("{}", err)),
+            };
+
+            let failed = msg.is_err();
+            if tx.send(msg).await.is_err() || failed {
+                return;
+            }
+        }
+    }
+}
+
+pub struct Connection {
+    client: AsyncClient,
+    qos: QoS,
+    incoming: mpsc::Receiver<Result<Message>>,
+}
+
+#[async_trait::async_trait]
+impl super::Connection for Connection {
+    async fn publish(&mut self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        self.client.publish(topic, self.qos, false, payload).await?;
+
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<Message> {
+        match self.incoming.recv().await {
+            Some(msg) => msg,
+            None => Err(anyhow!("connection closed")),
+        }
+    }
+}
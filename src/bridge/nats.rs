@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_nats::{ConnectOptions, Event};
+use futures_util::StreamExt;
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+
+use super::{Message, BUFFER_SIZE};
+
+pub struct Connector {
+    url: String,
+}
+
+impl Connector {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl super::Connector for Connector {
+    async fn connect(&self, subscriptions: &[String]) -> Result<Box<dyn super::Connection>> {
+        // The client reconnects on its own and holds back the messages meanwhile, the
+        // bridge is told about the disconnect instead so that it gets to back off and
+        // account for the messages it drops
+        let (disconnected_tx, disconnected) = watch::channel(false);
+        let disconnected_tx = Arc::new(disconnected_tx);
+        let client = ConnectOptions::new()
+            .event_callback(move |event| {
+                let disconnected_tx = Arc::clone(&disconnected_tx);
+                async move {
+                    if matches!(event, Event::Disconnected) {
+                        let _ = disconnected_tx.send(true);
+                    }
+                }
+            })
+            .connect(self.url.as_str())
+            .await?;
+
+        let (tx, rx) = mpsc::channel(BUFFER_SIZE);
+        for subject in subscriptions {
+            let mut subscriber = client.subscribe(subject.clone()).await?;
+            let tx = tx.clone();
+
+            // The subscriber is unsubscribed once it is dropped along with this task
+            tokio::spawn(async move {
+                while let Some(msg) = subscriber.next().await {
+                    let msg = (msg.subject.to_string(), msg.payload.to_vec());
+                    if tx.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Ok(Box::new(Connection {
+            client,
+            incoming: rx,
+            disconnected,
+        }))
+    }
+}
+
+pub struct Connection {
+    client: async_nats::Client,
+    incoming: mpsc::Receiver<Message>,
+    disconnected: watch::Receiver<bool>,
+}
+
+#[async_trait::async_trait]
+impl super::Connection for Connection {
+    async fn publish(&mut self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        self.client
+            .publish(topic.to_string(), payload.into())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<Message> {
+        select! {
+            msg = self.incoming.recv() => msg.ok_or_else(|| anyhow!("connection closed")),
+            _ = self.disconnected.wait_for(|disconnected| *disconnected) => {
+                Err(anyhow!("disconnected from the server"))
+            }
+        }
+    }
+}
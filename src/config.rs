@@ -11,6 +11,38 @@ impl Config {
         Config::get_any("HYPERION_PORT", "2310")
     }
 
+    pub fn get_metrics_port() -> String {
+        Config::get_any("HYPERION_METRICS_PORT", "2311")
+    }
+
+    pub fn get_state_dir() -> String {
+        Config::get_any("HYPERION_STATE_DIR", "/var/lib/hyperion")
+    }
+
+    pub fn get_orphan_policy() -> String {
+        Config::get_any("HYPERION_ORPHAN_POLICY", "terminate")
+    }
+
+    pub fn get_subreaper() -> String {
+        Config::get_any("HYPERION_SUBREAPER", "false")
+    }
+
+    pub fn get_bridge_url() -> String {
+        Config::get_any("HYPERION_BRIDGE_URL", "")
+    }
+
+    pub fn get_bridge_exports() -> String {
+        Config::get_any("HYPERION_BRIDGE_EXPORTS", "")
+    }
+
+    pub fn get_bridge_imports() -> String {
+        Config::get_any("HYPERION_BRIDGE_IMPORTS", "")
+    }
+
+    pub fn get_bridge_qos() -> String {
+        Config::get_any("HYPERION_BRIDGE_QOS", "0")
+    }
+
     pub fn get_any(key: &str, fallback: &str) -> String {
         match env::var(key) {
             Ok(res) => res,
@@ -1,5 +1,7 @@
 mod actor;
+mod bridge;
 mod config;
+mod metrics;
 mod proto;
 mod server;
 mod utility;
@@ -13,11 +15,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Setup logger
     env_logger::init();
     
+    // Deal with the wodules left behind by an earlier run
+    let orphans = woduler::process::Orphans::new(&Config::get_state_dir());
+    // Without the state directory the wodules of an earlier run cannot be found, which
+    // should not keep the daemon from serving
+    if let Err(err) = orphans.recover(Config::get_orphan_policy().parse()?).await {
+        log::error!("failed to recover the wodules of an earlier run: {}", err);
+    }
+
+    // Collect the processes orphaned by the wodules
+    if Config::get_subreaper().parse()? {
+        woduler::process::subreaper()?;
+    }
+
     // Create woduler manager
-    let manager = woduler::manager::Manager::new();
+    let mut manager = woduler::manager::Manager::new(orphans);
+
+    // Bridge the event bus to the external broker if one is configured
+    let bridge_url = Config::get_bridge_url();
+    if !bridge_url.is_empty() {
+        bridge::start(
+            bridge::Config {
+                url: bridge_url,
+                exports: bridge::Mapping::parse_list(&Config::get_bridge_exports())?,
+                imports: bridge::Mapping::parse_list(&Config::get_bridge_imports())?,
+                qos: Config::get_bridge_qos().parse()?,
+            },
+            manager.bus(),
+        )?;
+    }
+
     // Start the manager actor
     let manager_mailbox = manager.start();
 
+    // Create metrics server
+    let metrics_mailbox = manager_mailbox.clone();
+    tokio::spawn(async move {
+        if let Err(err) = metrics::start(metrics::Config {
+            host: &Config::get_host(),
+            port: &Config::get_metrics_port(),
+            mailbox: metrics_mailbox,
+        })
+        .await
+        {
+            log::error!("metrics server failed: {}", err);
+        }
+    });
+
     // Create api server
     server::start(server::Config {
         host: &Config::get_host(),
@@ -0,0 +1,143 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use tonic::codegen::{
+    http::{HeaderMap, Request, Response},
+    Body,
+};
+use tower::{Layer, Service};
+
+use super::{GRPC_LATENCY, GRPC_REQUESTS};
+
+/// GrpcMetricsLayer wraps the API server and records the count and latency
+/// of the gRPC requests
+#[derive(Clone, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetrics { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<GrpcBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let method = req.uri().path().to_string();
+        let start = Instant::now();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+
+            GRPC_LATENCY
+                .with_label_values(&[&method])
+                .observe(start.elapsed().as_secs_f64());
+
+            let mut call = Call {
+                method,
+                done: false,
+            };
+            match res {
+                Ok(res) => {
+                    // Calls which fail right away carry the status in the headers
+                    // while the rest carry it in the trailers sent after the body
+                    if let Some(code) = status(res.headers()) {
+                        call.finish(code);
+                    }
+
+                    Ok(res.map(|inner| GrpcBody { inner, call }))
+                }
+                Err(err) => {
+                    call.finish("transport");
+                    Err(err)
+                }
+            }
+        })
+    }
+}
+
+/// Call counts the request once its status is known
+struct Call {
+    method: String,
+    done: bool,
+}
+
+impl Call {
+    fn finish(&mut self, code: &str) {
+        if !self.done {
+            self.done = true;
+            GRPC_REQUESTS.with_label_values(&[&self.method, code]).inc();
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        // The body was dropped before it was sent in full, e.g. as the client went
+        // away in the middle of a stream, which gRPC reports as cancelled
+        self.finish("1");
+    }
+}
+
+fn status(headers: &HeaderMap) -> Option<&str> {
+    headers.get("grpc-status")?.to_str().ok()
+}
+
+/// GrpcBody is the body of a response which counts the request once its trailers
+/// are sent
+pub struct GrpcBody<B> {
+    inner: B,
+    call: Call,
+}
+
+impl<B: Body + Unpin> Body for GrpcBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let res = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let Poll::Ready(Ok(trailers)) = &res {
+            let code = trailers.as_ref().and_then(status).unwrap_or("0");
+            self.call.finish(code);
+        }
+
+        res
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
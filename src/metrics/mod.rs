@@ -0,0 +1,143 @@
+mod grpc;
+mod service;
+
+pub use grpc::*;
+pub use service::*;
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge_vec, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge_vec, GaugeVec, Histogram, HistogramVec,
+    IntCounter, IntCounterVec, IntGaugeVec,
+};
+
+/// UNSUBSCRIBED_TOPIC is the topic label of the messages published to a topic which
+/// has no subscribers, they are counted together to keep the number of series bounded
+pub const UNSUBSCRIBED_TOPIC: &str = "_unsubscribed";
+
+lazy_static! {
+    pub static ref MODULES: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_modules",
+        "Number of modules in each phase",
+        &["phase"]
+    )
+    .unwrap();
+    pub static ref MODULE_RESTARTS: IntCounterVec = register_int_counter_vec!(
+        "hyperion_module_restarts_total",
+        "Number of times the process of a module has been restarted",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_LAST_EXIT_CODE: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_module_last_exit_code",
+        "Exit code of the last process of a module, 128 + signal if it was killed by a signal",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_CPU_SECONDS: GaugeVec = register_gauge_vec!(
+        "hyperion_module_cpu_seconds",
+        "CPU time consumed by the process tree of a module",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_RSS_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_module_rss_bytes",
+        "Resident memory of the process tree of a module",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_OPEN_FDS: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_module_open_fds",
+        "Number of file descriptors opened by the process tree of a module",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_THREADS: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_module_threads",
+        "Number of threads in the process tree of a module",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref MODULE_IO_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_module_io_bytes",
+        "Bytes read from and written to storage by the process tree of a module",
+        &["module", "direction"]
+    )
+    .unwrap();
+    pub static ref DOWNLOAD_DURATION: Histogram = register_histogram!(
+        "hyperion_download_duration_seconds",
+        "Time taken to download module binaries"
+    )
+    .unwrap();
+    pub static ref DOWNLOAD_FAILURES: IntCounter = register_int_counter!(
+        "hyperion_download_failures_total",
+        "Number of module binary downloads which failed"
+    )
+    .unwrap();
+    pub static ref BUS_DEAD_LETTERS: IntCounterVec = register_int_counter_vec!(
+        "hyperion_bus_dead_letters_total",
+        "Number of messages which could not be delivered and were routed to a dead letter topic",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref BUS_PUBLISHED: IntCounterVec = register_int_counter_vec!(
+        "hyperion_bus_published_total",
+        "Number of messages published to a topic of the event bus, the series of a topic are removed once it has no subscribers left",
+        &["topic"]
+    )
+    .unwrap();
+    pub static ref BUS_DELIVERED: IntCounterVec = register_int_counter_vec!(
+        "hyperion_bus_delivered_total",
+        "Number of messages delivered to the subscribers of a topic",
+        &["topic"]
+    )
+    .unwrap();
+    pub static ref BUS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "hyperion_bus_dropped_total",
+        "Number of messages which could not be delivered to the subscribers of a topic",
+        &["topic"]
+    )
+    .unwrap();
+    pub static ref GRPC_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "hyperion_grpc_requests_total",
+        "Number of gRPC requests handled by the API server",
+        &["method", "code"]
+    )
+    .unwrap();
+    pub static ref GRPC_LATENCY: HistogramVec = register_histogram_vec!(
+        "hyperion_grpc_request_duration_seconds",
+        "Time taken by the API server to respond to a gRPC request",
+        &["method"]
+    )
+    .unwrap();
+    pub static ref MAILBOX_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "hyperion_mailbox_depth",
+        "Number of messages waiting in the mailbox of an actor",
+        &["actor"]
+    )
+    .unwrap();
+}
+
+/// exit_code takes in an exit status and returns the exit code reported for it, processes
+/// killed by a signal are reported the way shells do - 128 + signal
+pub fn exit_code(status: &ExitStatus) -> i64 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code as i64,
+        (None, Some(signal)) => 128 + signal as i64,
+        (None, None) => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(&ExitStatus::from_raw(9)), 137);
+    }
+}
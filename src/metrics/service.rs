@@ -0,0 +1,72 @@
+use std::convert::Infallible;
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use prometheus::{Encoder, TextEncoder};
+
+use super::MAILBOX_DEPTH;
+use crate::{actor, woduler::manager::command::Command};
+
+pub struct Config<'a> {
+    pub host: &'a str,
+    pub port: &'a str,
+
+    pub mailbox: actor::MailBox<Command>,
+}
+
+/// start starts the metrics server on the host and port given in the function
+/// parameter, metrics are served in the prometheus text format on `/metrics`
+pub async fn start(cfg: Config<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = format!("{}:{}", cfg.host, cfg.port).parse()?;
+    let mailbox = cfg.mailbox;
+
+    let make_svc = make_service_fn(move |_| {
+        let mailbox = mailbox.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let mailbox = mailbox.clone();
+
+                async move { Ok::<_, Infallible>(handle(req, &mailbox)) }
+            }))
+        }
+    });
+
+    log::info!("metrics server listening on {}", addr);
+
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+
+    Ok(())
+}
+
+fn handle(req: Request<Body>, mailbox: &actor::MailBox<Command>) -> Response<Body> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    // Gauges which are cheaper to sample than to keep up to date
+    MAILBOX_DEPTH
+        .with_label_values(&["manager"])
+        .set(mailbox.depth() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buf) {
+        log::error!("failed to encode metrics: {}", err);
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    Response::builder()
+        .header("Content-Type", encoder.format_type())
+        .body(Body::from(buf))
+        .unwrap()
+}
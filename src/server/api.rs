@@ -1,18 +1,31 @@
-use tokio::sync::{mpsc, oneshot};
+use prost::Message;
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, mpsc, oneshot},
+};
 // use futures_core::Stream;
 // use futures_util::StreamExt;
 // use tokio::sync::{mpsc, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
 use crate::{
-    actor,
+    actor, metrics,
     proto::api::{
-        hyperion_api_service_server::HyperionApiService as HyperionAPI, ApplyRequest,
-        ApplyResponse, DeleteRequest, DeleteResponse, GetRequest, GetResponse, ListRequest,
-        WatchDataRequest, WatchDataResponse, WatchLogRequest, WatchLogResponse,
+        self, hyperion_api_service_server::HyperionApiService as HyperionAPI, ApplyRequest,
+        ApplyResponse, AttachRequest, AttachResponse, DeleteRequest, DeleteResponse,
+        DescribeTopicRequest, DescribeTopicResponse, ExecRequest, ExecResponse, GetRequest,
+        GetResponse, ListRequest, ListTopicsRequest, ListTopicsResponse, PauseRequest,
+        PauseResponse, PublishRequest, PublishResponse, RestartRequest, RestartResponse,
+        ResumeRequest, ResumeResponse, SecretsRequest, SecretsResponse, SignalRequest,
+        SignalResponse, WatchDataRequest, WatchDataResponse, WatchLogRequest, WatchLogResponse,
+    },
+    woduler::{
+        manager::command::{self, Command},
+        process::{exec, parse_signal, Input},
+        selector::Selector,
+        validation::Violations,
     },
-    woduler::manager::command::{self, Command},
 };
 
 pub struct HyperionAPIService {
@@ -28,32 +41,15 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(module) = req.module {
-            let (tx, rx) = oneshot::channel();
-            if let Err(e) = self.mailbox.mail(command::Command::Apply(module, tx)).await {
-                log::error!("failed to communicate with woduler: {}", e);
-                return Err(tonic::Status::new(
-                    tonic::Code::Internal,
-                    "failed to proceess the request",
-                ));
-            }
-
-            match rx.await {
-                Ok(res) => match res {
-                    Ok(v) => {
-                        return Ok(Response::new(ApplyResponse { msg: v }));
-                    }
-                    Err(err) => {
-                        return Err(tonic::Status::new(tonic::Code::Internal, err.to_string()))
-                    }
-                },
-                Err(e) => {
-                    log::error!("failed to receive response from woduler: {}", e);
-                    return Err(tonic::Status::new(
-                        tonic::Code::Internal,
-                        "failed to process the request",
-                    ));
-                }
-            }
+            let msg = self
+                .request(|tx| command::Command::Apply(Box::new(module), tx))
+                .await?
+                .map_err(|err| match err.downcast_ref::<Violations>() {
+                    Some(violations) => Self::bad_request(violations),
+                    None => tonic::Status::new(tonic::Code::Internal, err.to_string()),
+                })?;
+
+            return Ok(Response::new(ApplyResponse { msg }));
         }
 
         Err(tonic::Status::new(
@@ -69,32 +65,12 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(core) = req.core {
-            let (tx, rx) = oneshot::channel();
-            if let Err(e) = self.mailbox.mail(command::Command::Delete(core, tx)).await {
-                log::error!("failed to communicate with woduler: {}", e);
-                return Err(tonic::Status::new(
-                    tonic::Code::Internal,
-                    "failed to proceess the request",
-                ));
-            }
+            let msg = self
+                .request(|tx| command::Command::Delete(core, tx))
+                .await?
+                .map_err(|err| tonic::Status::new(tonic::Code::Internal, err.to_string()))?;
 
-            match rx.await {
-                Ok(v) => match v {
-                    Ok(msg) => {
-                        return Ok(Response::new(DeleteResponse { msg }));
-                    }
-                    Err(err) => {
-                        return Err(tonic::Status::new(tonic::Code::Internal, err.to_string()));
-                    }
-                },
-                Err(e) => {
-                    log::error!("failed to receive response from woduler: {}", e);
-                    return Err(tonic::Status::new(
-                        tonic::Code::Internal,
-                        "failed to process the request",
-                    ));
-                }
-            }
+            return Ok(Response::new(DeleteResponse { msg }));
         }
 
         Err(tonic::Status::new(
@@ -112,15 +88,13 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(filter) = req.filter {
-            let (tx, mut rx) = mpsc::channel(8);
-            if let Err(e) = self.mailbox.mail(command::Command::List(filter, tx)).await {
-                log::error!("failed to communicate with woduler: {}", e);
-                return Err(tonic::Status::new(
-                    tonic::Code::Internal,
-                    "failed to proceess the request",
-                ));
+            if let api::list_request::Filter::Label(label) = &filter {
+                Selector::from_proto(label).map_err(Self::invalid_argument)?;
             }
 
+            let (tx, mut rx) = mpsc::channel(8);
+            self.send(command::Command::List(filter, tx)).await?;
+
             let (rtx, rrx) = mpsc::channel(8);
             tokio::spawn(async move {
                 while let Some(res) = rx.recv().await {
@@ -144,32 +118,14 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(core) = req.core {
-            let (tx, rx) = oneshot::channel();
-            if let Err(e) = self.mailbox.mail(command::Command::Get(core, tx)).await {
-                log::error!("failed to communicate with woduler: {}", e);
-                return Err(tonic::Status::new(
-                    tonic::Code::Internal,
-                    "failed to proceess the request",
-                ));
-            }
-
-            match rx.await {
-                Ok(v) => match v {
-                    Ok(res) => {
-                        return Ok(Response::new(GetResponse { module: Some(res) }));
-                    }
-                    Err(err) => {
-                        return Err(tonic::Status::new(tonic::Code::Internal, err.to_string()));
-                    }
-                },
-                Err(e) => {
-                    log::error!("failed to receive response from woduler: {}", e);
-                    return Err(tonic::Status::new(
-                        tonic::Code::Internal,
-                        "failed to process the request",
-                    ));
-                }
-            }
+            let module = self
+                .request(|tx| command::Command::Get(core, tx))
+                .await?
+                .map_err(|err| tonic::Status::new(tonic::Code::Internal, err.to_string()))?;
+
+            return Ok(Response::new(GetResponse {
+                module: Some(module),
+            }));
         }
 
         Err(tonic::Status::new(
@@ -187,18 +143,21 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(filter) = req.filter {
+            let filter = match filter {
+                api::watch_data_request::Filter::Core(core) => command::WatchFilter::Core(core),
+                api::watch_data_request::Filter::Label(label) => {
+                    Selector::from_proto(&label).map_err(Self::invalid_argument)?;
+                    command::WatchFilter::Label(label)
+                }
+            };
+
             let (tx, mut rx) = mpsc::channel(8);
-            if let Err(e) = self
-                .mailbox
-                .mail(command::Command::WatchData(filter, tx))
-                .await
-            {
-                log::error!("failed to communicate with woduler: {}", e);
-                return Err(tonic::Status::new(
-                    tonic::Code::Internal,
-                    "failed to proceess the request",
-                ));
-            }
+            let cmd = if req.dead_letter {
+                command::Command::WatchDeadLetter(filter, tx)
+            } else {
+                command::Command::WatchData(filter, tx)
+            };
+            self.send(cmd).await?;
 
             let (rtx, rrx) = mpsc::channel(8);
             tokio::spawn(async move {
@@ -228,23 +187,214 @@ impl HyperionAPI for HyperionAPIService {
         let req = request.into_inner();
 
         if let Some(filter) = req.filter {
+            let filter = match filter {
+                api::watch_log_request::Filter::Core(core) => command::WatchFilter::Core(core),
+                api::watch_log_request::Filter::Label(label) => {
+                    Selector::from_proto(&label).map_err(Self::invalid_argument)?;
+                    command::WatchFilter::Label(label)
+                }
+            };
+
             let (tx, mut rx) = mpsc::channel(8);
-            if let Err(e) = self
-                .mailbox
-                .mail(command::Command::WatchLog(filter, tx))
-                .await
-            {
-                log::error!("failed to communicate with woduler: {}", e);
+            self.send(command::Command::WatchLog(filter, tx)).await?;
+
+            let (rtx, rrx) = mpsc::channel(8);
+            tokio::spawn(async move {
+                while let Some(res) = rx.recv().await {
+                    if let Err(err) = rtx.send(Ok(WatchLogResponse { data: res })).await {
+                        log::warn!("failed to pipe data to the output stream: {}", err);
+                        break;
+                    }
+                }
+            });
+
+            return Ok(Response::new(ReceiverStream::new(rrx)));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    async fn publish(
+        &self,
+        request: Request<Streaming<PublishRequest>>,
+    ) -> Result<Response<PublishResponse>, Status> {
+        let mut stream = request.into_inner();
+
+        let (tx, rx) = mpsc::channel(8);
+        let (rtx, rrx) = oneshot::channel();
+        self.send(command::Command::Publish(rx, rtx)).await?;
+
+        while let Some(req) = stream.message().await? {
+            match &req.target {
+                Some(api::publish_request::Target::Core(core)) if !core.name.is_empty() => {}
+                Some(api::publish_request::Target::Topic(topic)) if !topic.is_empty() => {}
+                _ => {
+                    return Err(tonic::Status::new(
+                        tonic::Code::InvalidArgument,
+                        "invalid request - target module or topic is required",
+                    ));
+                }
+            }
+
+            if tx.send(req).await.is_err() {
+                log::error!("failed to pipe data to the woduler");
                 return Err(tonic::Status::new(
                     tonic::Code::Internal,
-                    "failed to proceess the request",
+                    "failed to process the request",
                 ));
             }
+        }
+
+        // Let the woduler know that the client is done publishing
+        drop(tx);
+
+        let published = Self::reply(rrx).await?;
+        Ok(Response::new(PublishResponse { published }))
+    }
+
+    async fn list_topics(
+        &self,
+        _request: Request<ListTopicsRequest>,
+    ) -> Result<Response<ListTopicsResponse>, Status> {
+        let topics = self.request(command::Command::ListTopics).await?;
+
+        Ok(Response::new(ListTopicsResponse { topics }))
+    }
+
+    async fn describe_topic(
+        &self,
+        request: Request<DescribeTopicRequest>,
+    ) -> Result<Response<DescribeTopicResponse>, Status> {
+        let req = request.into_inner();
+
+        if !req.topic.is_empty() {
+            let topic = self
+                .request(|tx| command::Command::DescribeTopic(req.topic, tx))
+                .await?
+                .map_err(|err| tonic::Status::new(tonic::Code::NotFound, err.to_string()))?;
+
+            return Ok(Response::new(DescribeTopicResponse { topic: Some(topic) }));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    type AttachStream = ReceiverStream<Result<AttachResponse, Status>>;
+
+    async fn attach(
+        &self,
+        request: Request<Streaming<AttachRequest>>,
+    ) -> Result<Response<Self::AttachStream>, Status> {
+        let mut stream = request.into_inner();
+
+        // The first message selects the module to attach to
+        let first = match stream.message().await? {
+            Some(req) if matches!(&req.core, Some(core) if !core.name.is_empty()) => req,
+            _ => {
+                return Err(tonic::Status::new(
+                    tonic::Code::InvalidArgument,
+                    "invalid request - the first message must select the module",
+                ));
+            }
+        };
+
+        let terminal = self
+            .request(|tx| command::Command::Attach(first.core.clone().unwrap(), first.replica, tx))
+            .await?
+            .map_err(Self::failed_precondition)?;
+        let (mut output, input) = terminal.attach();
+
+        // Pipe the input of the client into the terminal
+        tokio::spawn(async move {
+            let mut req = Some(first);
+
+            while let Some(AttachRequest {
+                input: data,
+                resize,
+                ..
+            }) = req
+            {
+                if let Some(size) = resize {
+                    let resize = Input::Resize {
+                        rows: size.rows as u16,
+                        cols: size.cols as u16,
+                    };
+                    if input.send(resize).await.is_err() {
+                        break;
+                    }
+                }
+
+                if !data.is_empty() && input.send(Input::Data(data)).await.is_err() {
+                    break;
+                }
+
+                req = stream.message().await.ok().flatten();
+            }
+        });
+
+        // Pipe the output of the terminal to the client
+        let (rtx, rrx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            loop {
+                select! {
+                    res = output.recv() => match res {
+                        Ok(data) => {
+                            if rtx.send(Ok(AttachResponse { output: data })).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            log::warn!("attached client fell behind - skipped {} chunks of output", skipped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = rtx.closed() => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rrx)))
+    }
+
+    type ExecStream = ReceiverStream<Result<ExecResponse, Status>>;
+
+    async fn exec(
+        &self,
+        request: Request<ExecRequest>,
+    ) -> Result<Response<Self::ExecStream>, Status> {
+        let req = request.into_inner();
+
+        if let Some(core) = req.core {
+            if req.command.is_empty() {
+                return Err(tonic::Status::new(
+                    tonic::Code::InvalidArgument,
+                    "invalid request - command is required",
+                ));
+            }
+
+            let mut output = self
+                .request(|tx| command::Command::Exec(core, req.replica, req.command, tx))
+                .await?
+                .map_err(Self::failed_precondition)?;
 
             let (rtx, rrx) = mpsc::channel(8);
             tokio::spawn(async move {
-                while let Some(res) = rx.recv().await {
-                    if let Err(err) = rtx.send(Ok(WatchLogResponse { data: res })).await {
+                while let Some(res) = output.recv().await {
+                    let res = match res {
+                        exec::Output::Stdout(data) => api::exec_response::Output::Stdout(data),
+                        exec::Output::Stderr(data) => api::exec_response::Output::Stderr(data),
+                        exec::Output::Exit(status) => {
+                            api::exec_response::Output::ExitCode(metrics::exit_code(&status) as i32)
+                        }
+                    };
+
+                    if let Err(err) = rtx.send(Ok(ExecResponse { output: Some(res) })).await {
                         log::warn!("failed to pipe data to the output stream: {}", err);
                         break;
                     }
@@ -259,10 +409,207 @@ impl HyperionAPI for HyperionAPIService {
             "invalid request",
         ))
     }
+
+    async fn signal(
+        &self,
+        request: Request<SignalRequest>,
+    ) -> Result<Response<SignalResponse>, Status> {
+        let req = request.into_inner();
+
+        if let Some(core) = req.core {
+            parse_signal(&req.signal).map_err(Self::invalid_argument)?;
+
+            let msg = self
+                .request(|tx| command::Command::Signal(core, req.signal, tx))
+                .await?
+                .map_err(Self::failed_precondition)?;
+
+            return Ok(Response::new(SignalResponse { msg }));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    async fn restart(
+        &self,
+        request: Request<RestartRequest>,
+    ) -> Result<Response<RestartResponse>, Status> {
+        let req = request.into_inner();
+
+        if let Some(core) = req.core {
+            let msg = self
+                .request(|tx| command::Command::Restart(core, tx))
+                .await?
+                .map_err(Self::failed_precondition)?;
+
+            return Ok(Response::new(RestartResponse { msg }));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    async fn pause(
+        &self,
+        request: Request<PauseRequest>,
+    ) -> Result<Response<PauseResponse>, Status> {
+        let req = request.into_inner();
+
+        if let Some(core) = req.core {
+            let msg = self
+                .request(|tx| command::Command::Pause(core, tx))
+                .await?
+                .map_err(Self::failed_precondition)?;
+
+            return Ok(Response::new(PauseResponse { msg }));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    async fn resume(
+        &self,
+        request: Request<ResumeRequest>,
+    ) -> Result<Response<ResumeResponse>, Status> {
+        let req = request.into_inner();
+
+        if let Some(core) = req.core {
+            let msg = self
+                .request(|tx| command::Command::Resume(core, tx))
+                .await?
+                .map_err(Self::failed_precondition)?;
+
+            return Ok(Response::new(ResumeResponse { msg }));
+        }
+
+        Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            "invalid request",
+        ))
+    }
+
+    async fn secrets(
+        &self,
+        request: Request<SecretsRequest>,
+    ) -> Result<Response<SecretsResponse>, Status> {
+        let req = request.into_inner();
+
+        let names = self
+            .request(|tx| command::Command::Secrets(req.set, req.delete, tx))
+            .await?
+            .map_err(Self::invalid_argument)?;
+
+        Ok(Response::new(SecretsResponse { names }))
+    }
 }
 
 impl HyperionAPIService {
     pub fn new(mailbox: actor::MailBox<Command>) -> Self {
         HyperionAPIService { mailbox }
     }
+
+    /// send mails the command to the woduler
+    async fn send(&self, cmd: Command) -> Result<(), Status> {
+        self.mailbox.mail(cmd).await.map_err(|e| {
+            log::error!("failed to communicate with woduler: {}", e);
+            tonic::Status::new(tonic::Code::Internal, "failed to process the request")
+        })
+    }
+
+    /// reply waits for the woduler to respond to a command
+    async fn reply<T>(rx: oneshot::Receiver<T>) -> Result<T, Status> {
+        rx.await.map_err(|e| {
+            log::error!("failed to receive response from woduler: {}", e);
+            tonic::Status::new(tonic::Code::Internal, "failed to process the request")
+        })
+    }
+
+    /// request takes in a function which builds a command around the response channel,
+    /// mails the command to the woduler and waits for its response
+    async fn request<T, F>(&self, cmd: F) -> Result<T, Status>
+    where
+        F: FnOnce(oneshot::Sender<T>) -> Command,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.send(cmd(tx)).await?;
+
+        Self::reply(rx).await
+    }
+
+    /// invalid_argument takes in an error and returns an `InvalidArgument` status for it
+    fn invalid_argument<E: std::fmt::Display>(err: E) -> Status {
+        tonic::Status::new(tonic::Code::InvalidArgument, err.to_string())
+    }
+
+    /// failed_precondition takes in an error and returns a `FailedPrecondition` status
+    /// for it
+    fn failed_precondition<E: std::fmt::Display>(err: E) -> Status {
+        tonic::Status::new(tonic::Code::FailedPrecondition, err.to_string())
+    }
+
+    /// bad_request returns an `InvalidArgument` status for the violations of a module
+    /// definition, the violations are attached as a `google.rpc.BadRequest` so that
+    /// clients can point at the offending fields
+    fn bad_request(violations: &Violations) -> Status {
+        let message = violations.to_string();
+        let bad_request = rpc::BadRequest {
+            field_violations: violations
+                .0
+                .iter()
+                .map(|violation| rpc::FieldViolation {
+                    field: violation.field.clone(),
+                    description: violation.description.clone(),
+                })
+                .collect(),
+        };
+        let status = rpc::Status {
+            code: tonic::Code::InvalidArgument as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: "type.googleapis.com/google.rpc.BadRequest".to_string(),
+                value: bad_request.encode_to_vec(),
+            }],
+        };
+
+        tonic::Status::with_details(
+            tonic::Code::InvalidArgument,
+            message,
+            status.encode_to_vec().into(),
+        )
+    }
+}
+
+/// rpc holds the messages of `google/rpc` which are sent as the details of a status
+mod rpc {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Status {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(string, tag = "2")]
+        pub message: String,
+        #[prost(message, repeated, tag = "3")]
+        pub details: Vec<prost_types::Any>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BadRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub field_violations: Vec<FieldViolation>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FieldViolation {
+        #[prost(string, tag = "1")]
+        pub field: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+    }
 }
@@ -2,7 +2,7 @@ use tonic::transport::Server;
 
 use super::api::HyperionAPIService;
 use crate::{
-    actor, proto::api::hyperion_api_service_server::HyperionApiServiceServer,
+    actor, metrics, proto::api::hyperion_api_service_server::HyperionApiServiceServer,
     woduler::manager::command::Command,
 };
 
@@ -23,7 +23,11 @@ pub async fn start(cfg: Config<'_>) -> Result<(), Box<dyn std::error::Error>> {
 
     log::info!("server listening on {}", addr);
 
-    Server::builder().add_service(server).serve(addr).await?;
+    Server::builder()
+        .layer(metrics::GrpcMetricsLayer)
+        .add_service(server)
+        .serve(addr)
+        .await?;
 
     Ok(())
 }
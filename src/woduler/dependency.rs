@@ -0,0 +1,297 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures_util::future::select_all;
+use tokio::{
+    select,
+    sync::{watch, Mutex},
+};
+
+use crate::proto::base;
+
+use super::process::{Conditions, Controller};
+use super::selector::Selector;
+
+/// Modules maps the key of every module to its definition and the process controllers
+/// of its replicas
+pub type Modules = Arc<Mutex<HashMap<String, (base::Module, Vec<Controller>)>>>;
+
+/// Condition is the state that the modules a module depends on have to be in before
+/// the module is started
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    /// Running is met while the process of the module is running
+    Running,
+    /// Ready is met once the running process of the module has written to its stdout
+    Ready,
+    /// Completed is met once the process of the module has exited successfully
+    Completed,
+}
+
+impl std::str::FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "running" => Ok(Self::Running),
+            "ready" => Ok(Self::Ready),
+            "completed" => Ok(Self::Completed),
+            _ => Err(anyhow!(
+                "invalid dependency condition \"{}\" - expected \"running\", \"ready\" or \"completed\"",
+                s
+            )),
+        }
+    }
+}
+
+impl Condition {
+    /// is_met returns true if a module in the given conditions satisfies the condition
+    pub fn is_met(&self, conditions: &Conditions) -> bool {
+        match self {
+            Self::Running => conditions.running,
+            Self::Ready => conditions.ready,
+            Self::Completed => conditions.completed,
+        }
+    }
+}
+
+/// Target selects the modules which a module depends on
+#[derive(Clone, Debug)]
+pub enum Target {
+    Name(String),
+    /// Selector selects the modules by their labels, the module itself is never selected
+    Selector(Selector),
+}
+
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub target: Target,
+    pub condition: Condition,
+}
+
+impl Dependency {
+    /// from_module takes in a module definition and returns its dependencies
+    pub fn from_module(md: &base::Module) -> Result<Vec<Self>> {
+        let deps = match &md.spec {
+            Some(spec) => &spec.depends_on,
+            None => return Ok(Vec::new()),
+        };
+
+        deps.iter()
+            .map(|dep| {
+                let target = match (dep.name.is_empty(), &dep.selector) {
+                    (false, None) => Target::Name(dep.name.clone()),
+                    (true, Some(selector)) => Target::Selector(Selector::from_proto(selector)?),
+                    _ => return Err(anyhow!("dependency must have either a name or a selector")),
+                };
+
+                Ok(Self {
+                    target,
+                    condition: dep.condition.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    /// matches returns true if the dependency of the module with key `dependent`
+    /// selects the module with key `key`
+    pub fn matches(&self, dependent: &str, key: &str, md: &base::Module) -> bool {
+        match &self.target {
+            Target::Name(name) => name == key,
+            Target::Selector(selector) => key != dependent && selector.matches_module(md),
+        }
+    }
+}
+
+/// find_cycle takes in the key of a module along with all of the modules, including the
+/// module itself, and returns the keys along a dependency cycle through the module if
+/// there is one
+pub fn find_cycle(key: &str, modules: &HashMap<&str, &base::Module>) -> Option<Vec<String>> {
+    let mut path = vec![key.to_string()];
+    let mut visited = HashSet::new();
+
+    visit(key, key, modules, &mut path, &mut visited).then_some(path)
+}
+
+/// visit walks the dependencies of `node` depth first and returns true once it gets
+/// back to `root`, `path` is left holding the keys along the way
+fn visit<'a>(
+    root: &str,
+    node: &str,
+    modules: &HashMap<&'a str, &base::Module>,
+    path: &mut Vec<String>,
+    visited: &mut HashSet<&'a str>,
+) -> bool {
+    let deps = match modules.get(node) {
+        // The dependencies of the modules which are already running were validated
+        // when they were applied
+        Some(md) => Dependency::from_module(md).unwrap_or_default(),
+        None => return false,
+    };
+
+    for (key, md) in modules.iter() {
+        if !deps.iter().any(|dep| dep.matches(node, key, md)) {
+            continue;
+        }
+
+        path.push(key.to_string());
+        if *key == root || (visited.insert(*key) && visit(root, key, modules, path, visited)) {
+            return true;
+        }
+        path.pop();
+    }
+
+    false
+}
+
+/// gate spawns a task which keeps track of whether the dependencies of the module
+/// with key `dependent` are met and returns a receiver of it, the task quits once
+/// the receiver is dropped
+///
+/// The dependencies are reevaluated whenever `changes` is notified of a module being
+/// applied or deleted or whenever the conditions of a selected module change, a
+/// dependency which selects no module is not met
+pub fn gate(
+    dependent: String,
+    deps: Vec<Dependency>,
+    modules: Modules,
+    mut changes: watch::Receiver<()>,
+) -> watch::Receiver<bool> {
+    let (tx, rx) = watch::channel(deps.is_empty());
+    if deps.is_empty() {
+        return rx;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let mut watched = Vec::new();
+            let mut met = true;
+
+            for dep in deps.iter() {
+                let mut selected = false;
+
+                for (key, (md, controllers)) in modules.lock().await.iter() {
+                    if !dep.matches(&dependent, key, md) {
+                        continue;
+                    }
+
+                    // Every replica of the module has to meet the condition
+                    for pc in controllers {
+                        let conditions = pc.conditions();
+                        met &= dep.condition.is_met(&conditions.borrow());
+                        watched.push(conditions);
+                    }
+                    selected = true;
+                }
+
+                met &= selected;
+            }
+
+            tx.send_if_modified(|current| std::mem::replace(current, met) != met);
+
+            let changed = async {
+                if watched.is_empty() {
+                    std::future::pending::<()>().await;
+                } else {
+                    let _ = select_all(watched.iter_mut().map(|rx| Box::pin(rx.changed()))).await;
+                }
+            };
+
+            select! {
+                res = changes.changed() => if res.is_err() {
+                    return;
+                },
+                _ = changed => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(labels: &[(&str, &str)], deps: Vec<base::module_spec::Dependency>) -> base::Module {
+        base::Module {
+            metadata: Some(base::ModuleMetadata {
+                labels: labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            }),
+            spec: Some(base::ModuleSpec {
+                depends_on: deps,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn on_name(name: &str, condition: &str) -> base::module_spec::Dependency {
+        base::module_spec::Dependency {
+            name: name.to_string(),
+            condition: condition.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn on_label(key: &str, value: &str) -> base::module_spec::Dependency {
+        base::module_spec::Dependency {
+            selector: Some(base::LabelSelector {
+                selector: [(key.to_string(), value.to_string())].into_iter().collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_module() {
+        let deps = Dependency::from_module(&module(
+            &[],
+            vec![on_name("bpffs", "completed"), on_label("app", "producer")],
+        ))
+        .unwrap();
+        assert_eq!(deps[0].condition, Condition::Completed);
+        assert_eq!(deps[1].condition, Condition::Running);
+
+        let producer = module(&[("app", "producer")], vec![]);
+        assert!(deps[0].matches("consumer", "bpffs", &producer));
+        assert!(deps[1].matches("consumer", "producer", &producer));
+        assert!(!deps[1].matches("producer", "producer", &producer));
+
+        assert!(Dependency::from_module(&module(&[], vec![on_name("a", "done")])).is_err());
+        assert!(Dependency::from_module(&module(&[], vec![on_name("", "")])).is_err());
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let a = module(&[("tier", "a")], vec![on_name("b", "")]);
+        let b = module(&[("tier", "b")], vec![on_label("tier", "c")]);
+        let c = module(&[("tier", "c")], vec![]);
+        let mut modules: HashMap<&str, &base::Module> =
+            [("a", &a), ("b", &b), ("c", &c)].into_iter().collect();
+        assert_eq!(find_cycle("a", &modules), None);
+
+        let cyclic = module(&[("tier", "c")], vec![on_label("tier", "a")]);
+        modules.insert("c", &cyclic);
+        assert_eq!(
+            find_cycle("c", &modules),
+            Some(vec![
+                "c".to_string(),
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string()
+            ])
+        );
+
+        let itself = module(&[], vec![on_name("d", "")]);
+        modules.insert("d", &itself);
+        assert!(find_cycle("d", &modules).is_some());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+};
+
+use crate::woduler::process::mail::{DeadLetter, Mail};
+
+use super::bus::Bus;
+
+/// Distribution decides how the inputs of a module are spread across its replicas
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Broadcast hands every input to all of the replicas
+    Broadcast,
+    /// RoundRobin hands the inputs to the replicas in turns
+    RoundRobin,
+    /// KeyHash hands the inputs with the same key to the same replica, the key is the
+    /// data up to the first tab or all of the data if there is no tab
+    KeyHash,
+}
+
+impl std::str::FromStr for Distribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "broadcast" => Ok(Self::Broadcast),
+            "round-robin" => Ok(Self::RoundRobin),
+            "key-hash" => Ok(Self::KeyHash),
+            _ => Err(anyhow!(
+                "invalid distribution \"{}\" - expected \"broadcast\", \"round-robin\" or \"key-hash\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Balancer hands each of the inputs of a module to one of its replicas, the replicas
+/// whose process is not running are skipped unless they are paused
+#[derive(Clone)]
+pub struct Balancer {
+    distribution: Distribution,
+    replicas: usize,
+    attached: Arc<std::sync::Mutex<BTreeMap<usize, mpsc::Sender<Mail>>>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Balancer {
+    pub fn new(distribution: Distribution, replicas: usize) -> Self {
+        Self {
+            distribution,
+            replicas,
+            attached: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// attach starts handing the inputs meant for the replica to `tx`
+    pub fn attach(&self, replica: usize, tx: mpsc::Sender<Mail>) {
+        self.attached.lock().unwrap().insert(replica, tx);
+    }
+
+    /// detach stops handing inputs to the replica
+    pub fn detach(&self, replica: usize) {
+        self.attached.lock().unwrap().remove(&replica);
+    }
+
+    /// run spawns a task which hands the mails coming from `rx` to the replicas, the
+    /// mails which cannot be handed to any replica are published to the dead letter topic
+    pub fn run(&self, mut rx: mpsc::Receiver<Mail>, mut bus: Bus, dead_letter_topic: String) {
+        let balancer = self.clone();
+
+        tokio::spawn(async move {
+            while let Some(mail) = rx.recv().await {
+                if let Err(mail) = balancer.hand(mail).await {
+                    log::warn!("failed to hand message to a replica");
+
+                    // The topic on which the mail was published is not known past the pipes
+                    let letter = DeadLetter {
+                        reason: "no replica available to receive the message".to_string(),
+                        topic: String::new(),
+                        mail,
+                    };
+                    bus.dead_letter(&dead_letter_topic, letter).await;
+                }
+            }
+        });
+    }
+
+    /// hand hands the mail to the picked replica, waiting on it if it is busy, and
+    /// moves on to the next replica only if the picked one is gone, the mail is returned
+    /// if none of the replicas can take it
+    ///
+    /// With round-robin the turn of a busy replica goes to the next one which is not busy
+    /// first, the inputs with the same key always wait on their replica
+    async fn hand(&self, mut mail: Mail) -> Result<(), Mail> {
+        let replicas = self.pick(&mail);
+
+        if self.distribution == Distribution::RoundRobin {
+            for tx in replicas.iter() {
+                match tx.try_send(mail) {
+                    Ok(()) => return Ok(()),
+                    Err(TrySendError::Full(rejected)) | Err(TrySendError::Closed(rejected)) => {
+                        mail = rejected
+                    }
+                }
+            }
+        }
+
+        for tx in replicas {
+            match tx.send(mail).await {
+                Ok(()) => return Ok(()),
+                Err(SendError(rejected)) => mail = rejected,
+            }
+        }
+
+        Err(mail)
+    }
+
+    /// pick returns the pipes of the attached replicas, starting with the one which
+    /// should receive the mail
+    fn pick(&self, mail: &Mail) -> Vec<mpsc::Sender<Mail>> {
+        let attached = self.attached.lock().unwrap();
+        if attached.is_empty() {
+            return Vec::new();
+        }
+
+        let replica = match self.distribution {
+            Distribution::KeyHash => {
+                let payload = mail.payload();
+                let key = payload.split(|b| *b == b'\t').next().unwrap_or(payload);
+
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish() as usize % self.replicas
+            }
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % self.replicas,
+        };
+
+        // The inputs of a replica which is down go to the next one which is up
+        attached
+            .range(replica..)
+            .chain(attached.range(..replica))
+            .map(|(_, tx)| tx.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::select;
+
+    use super::*;
+
+    fn mail(data: &[u8]) -> Mail {
+        Mail {
+            typ: crate::woduler::process::mail::data_type::DATA,
+            size: data.len() as u64,
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pick() {
+        let balancer = Balancer::new(Distribution::RoundRobin, 3);
+        assert!(balancer.pick(&mail(b"a")).is_empty());
+
+        let (tx0, mut rx0) = mpsc::channel(1);
+        let (tx2, mut rx2) = mpsc::channel(1);
+        balancer.attach(0, tx0.clone());
+        balancer.attach(2, tx2.clone());
+
+        // Replica 1 is down hence its turn goes to replica 2
+        let picked: Vec<bool> = (0..3)
+            .map(|_| balancer.pick(&mail(b"a"))[0].same_channel(&tx0))
+            .collect();
+        assert_eq!(picked, vec![true, false, false]);
+
+        let balancer = Balancer::new(Distribution::KeyHash, 3);
+        balancer.attach(0, tx0.clone());
+        balancer.attach(2, tx2.clone());
+        let first = balancer.pick(&mail(b"key\tone"))[0].clone();
+        for data in [&b"key\ttwo"[..], b"key", b"key\tthree\tfour"] {
+            assert!(balancer.pick(&mail(data))[0].same_channel(&first));
+        }
+
+        // A busy replica is waited on rather than the key going to another replica
+        assert!(balancer.hand(mail(b"key")).await.is_ok());
+        let hand = balancer.hand(mail(b"key\tbusy"));
+        tokio::pin!(hand);
+        select! {
+            biased;
+            _ = &mut hand => panic!("mail was handed to another replica"),
+            _ = std::future::ready(()) => {}
+        }
+        let (rx, other) = match first.same_channel(&tx0) {
+            true => (&mut rx0, &mut rx2),
+            false => (&mut rx2, &mut rx0),
+        };
+        assert_eq!(rx.recv().await.unwrap().data, b"key");
+        assert!(hand.await.is_ok());
+        assert_eq!(rx.recv().await.unwrap().data, b"key\tbusy");
+        assert!(other.try_recv().is_err());
+
+        // The turn of a busy replica goes to the next one
+        let balancer = Balancer::new(Distribution::RoundRobin, 3);
+        balancer.attach(0, tx0.clone());
+        balancer.attach(2, tx2.clone());
+        assert!(tx0.try_send(mail(b"busy")).is_ok());
+        assert!(balancer.hand(mail(b"a")).await.is_ok());
+
+        // Once every replica is busy the mail waits for its turn instead of being dropped
+        let hand = balancer.hand(mail(b"b"));
+        tokio::pin!(hand);
+        select! {
+            biased;
+            _ = &mut hand => panic!("mail was handed to a busy replica"),
+            _ = std::future::ready(()) => {}
+        }
+        assert_eq!(rx2.recv().await.unwrap().data, b"a");
+        assert!(hand.await.is_ok());
+        assert_eq!(rx2.recv().await.unwrap().data, b"b");
+        assert_eq!(rx0.recv().await.unwrap().data, b"busy");
+
+        balancer.detach(0);
+        balancer.detach(2);
+        assert!(balancer.pick(&mail(b"key")).is_empty());
+    }
+}
@@ -3,13 +3,70 @@ use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{mpsc, Mutex};
 use uuid::Uuid;
 
-use crate::woduler::process::mail::Mail;
+use crate::{
+    metrics,
+    woduler::process::mail::{DeadLetter, Mail},
+};
+
+use super::stats::{Rate, SubscriptionCounters, SubscriptionStats, TopicStats};
+
+const SUBSCRIPTION_BUFFER_SIZE: usize = 8;
 
 type DataChannel = mpsc::Sender<Mail>;
-type TopicSubscriber = HashMap<u128, DataChannel>;
+
+struct Subscriber {
+    tx: DataChannel,
+    dead_letter_topic: Option<String>,
+    counters: Arc<SubscriptionCounters>,
+}
+
+impl Subscriber {
+    fn stats(&self, id: u128) -> SubscriptionStats {
+        // Messages still waiting to be sent plus the ones sitting in the channel
+        let buffered = SUBSCRIPTION_BUFFER_SIZE - self.tx.capacity();
+
+        SubscriptionStats {
+            id,
+            delivered: self.counters.delivered(),
+            dropped: self.counters.dropped(),
+            queued: self.counters.pending() + buffered as u64,
+            dead_letter_topic: self.dead_letter_topic.clone(),
+        }
+    }
+}
+
+struct TopicSubscriber {
+    subscribers: HashMap<u128, Subscriber>,
+    published: u64,
+    rate: Rate,
+}
+
+impl TopicSubscriber {
+    fn new() -> Self {
+        Self {
+            subscribers: HashMap::new(),
+            published: 0,
+            rate: Rate::new(),
+        }
+    }
+
+    fn stats(&self, topic: &str) -> TopicStats {
+        TopicStats {
+            topic: topic.to_string(),
+            published: self.published,
+            rate: self.rate.get(),
+            subscriptions: self
+                .subscribers
+                .iter()
+                .map(|(id, subscriber)| subscriber.stats(*id))
+                .collect(),
+        }
+    }
+}
 
 pub struct Bus {
     subscribers: Arc<Mutex<HashMap<String, TopicSubscriber>>>,
+    dead_letters: Arc<Mutex<HashMap<String, u64>>>,
 }
 
 impl Bus {
@@ -17,11 +74,25 @@ impl Bus {
     pub fn new() -> Self {
         Self {
             subscribers: Arc::new(Mutex::new(HashMap::new())),
+            dead_letters: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     /// subscribe takes in a topic and returns a subscription id and message receiver
     pub async fn subscribe(&mut self, topic: String) -> (u128, mpsc::Receiver<Mail>) {
+        self.subscribe_with_dead_letter(topic, None).await
+    }
+
+    /// subscribe_with_dead_letter takes in a topic and a dead letter topic and returns a
+    /// subscription id and message receiver
+    ///
+    /// Messages which fail to be delivered to the subscriber are published to the dead
+    /// letter topic, if there is one
+    pub async fn subscribe_with_dead_letter(
+        &mut self,
+        topic: String,
+        dead_letter_topic: Option<String>,
+    ) -> (u128, mpsc::Receiver<Mail>) {
         // Create ID for this subscription
         let id = Uuid::new_v4().as_u128();
 
@@ -32,18 +103,23 @@ impl Bus {
         );
 
         // Create channel for the subscription
-        let (tx, rx) = mpsc::channel(8);
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+        let subscriber = Subscriber {
+            tx,
+            dead_letter_topic,
+            counters: Arc::new(SubscriptionCounters::default()),
+        };
 
         let mut locked = self.subscribers.lock().await;
         match locked.get_mut(&topic) {
             Some(subs_grp) => {
-                subs_grp.insert(id, tx);
+                subs_grp.subscribers.insert(id, subscriber);
             }
             None => {
-                let mut map = HashMap::new();
-                map.insert(id, tx);
+                let mut subs_grp = TopicSubscriber::new();
+                subs_grp.subscribers.insert(id, subscriber);
 
-                locked.insert(topic, map);
+                locked.insert(topic, subs_grp);
             }
         }
 
@@ -54,22 +130,122 @@ impl Bus {
     pub async fn publish(&mut self, topic: &str, data: Mail) {
         let mut locked = self.subscribers.lock().await;
         if let Some(subs_grp) = locked.get_mut(topic) {
-            for (id, v) in subs_grp.iter() {
-                let tx = v.clone();
+            metrics::BUS_PUBLISHED.with_label_values(&[topic]).inc();
+            subs_grp.published += 1;
+            subs_grp.rate.tick();
+
+            // The counters are looked up while the topic exists so that a delivery
+            // finishing after the topic was abandoned does not bring its series back
+            let delivered = metrics::BUS_DELIVERED.with_label_values(&[topic]);
+            let dropped = metrics::BUS_DROPPED.with_label_values(&[topic]);
+
+            for (id, v) in subs_grp.subscribers.iter() {
+                let tx = v.tx.clone();
+                let dead_letter_topic = v.dead_letter_topic.clone();
+                let counters = Arc::clone(&v.counters);
+                let (delivered, dropped) = (delivered.clone(), dropped.clone());
+                let topic = topic.to_string();
                 let data = data.clone();
+                let bus = self.clone();
 
                 log::debug!("publishing data for topic: {} to sid: {}", topic, id);
 
                 // Don't block the publish because of a slow consumer
+                counters.enqueue();
                 tokio::spawn(async move {
-                    if tx.send(data).await.is_err() {
+                    if let Err(err) = tx.send(data).await {
+                        counters.fail();
+                        dropped.inc();
                         log::warn!("failed to send message to subscriber");
+
+                        if let Some(dead_letter_topic) = dead_letter_topic {
+                            let letter = DeadLetter {
+                                reason: "failed to send message to subscriber".to_string(),
+                                topic,
+                                mail: err.0,
+                            };
+                            bus.route_dead_letter(dead_letter_topic, letter);
+                        }
+                    } else {
+                        counters.deliver();
+                        delivered.inc();
                     }
                 });
             }
+        } else {
+            metrics::BUS_PUBLISHED
+                .with_label_values(&[metrics::UNSUBSCRIBED_TOPIC])
+                .inc();
         }
     }
 
+    /// dead_letter takes in a dead letter topic and a message which could not be delivered
+    /// and publishes the message to the dead letter topic
+    pub async fn dead_letter(&mut self, topic: &str, letter: DeadLetter) {
+        log::debug!(
+            "routing undeliverable message from topic: {} to: {} - {}",
+            letter.topic,
+            topic,
+            letter.reason
+        );
+
+        *self
+            .dead_letters
+            .lock()
+            .await
+            .entry(topic.to_string())
+            .or_default() += 1;
+        // The reasons are a fixed set of messages hence the label stays bounded
+        metrics::BUS_DEAD_LETTERS
+            .with_label_values(&[&letter.reason])
+            .inc();
+
+        self.publish(topic, letter.into_mail()).await;
+    }
+
+    /// route_dead_letter publishes the message to the dead letter topic in the background
+    ///
+    /// This is the only way to dead letter from within `publish` as the future of `publish`
+    /// cannot be awaited from within itself
+    fn route_dead_letter(&self, topic: String, letter: DeadLetter) {
+        let mut bus = self.clone();
+
+        tokio::spawn(async move {
+            bus.dead_letter(&topic, letter).await;
+        });
+    }
+
+    /// dead_letters returns the number of messages which have been published to the given
+    /// dead letter topic
+    pub async fn dead_letters(&self, topic: &str) -> u64 {
+        self.dead_letters
+            .lock()
+            .await
+            .get(topic)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// topics returns a snapshot of all of the topics which have at least one subscriber
+    pub async fn topics(&self) -> Vec<TopicStats> {
+        self.subscribers
+            .lock()
+            .await
+            .iter()
+            .map(|(topic, subs_grp)| subs_grp.stats(topic))
+            .collect()
+    }
+
+    /// describe_topic takes in a topic and returns a snapshot of it, if the topic
+    /// has no subscribers then `None` is returned
+    pub async fn describe_topic(&self, topic: &str) -> Option<TopicStats> {
+        self.subscribers
+            .lock()
+            .await
+            .get(topic)
+            .map(|subs_grp| subs_grp.stats(topic))
+    }
+
     /// unsubscribe takes in a topic and the subscriber ID and removes that subscriber from the
     /// subscribed list
     pub async fn unsubscribe(&mut self, topic: &str, sub_id: u128) {
@@ -83,8 +259,8 @@ impl Bus {
         );
 
         if let Some(subs_grp) = locked.get_mut(topic) {
-            subs_grp.remove(&sub_id);
-            abandoned_topic = subs_grp.is_empty();
+            subs_grp.subscribers.remove(&sub_id);
+            abandoned_topic = subs_grp.subscribers.is_empty();
         }
 
         // If there are no subscribers for this topic then delete the entry to prevent memory leakage
@@ -95,6 +271,17 @@ impl Bus {
                 sub_id
             );
             locked.remove(topic);
+
+            // Topics come and go with the modules hence their series and their count of
+            // dead letters go along with them
+            for metric in [
+                &*metrics::BUS_PUBLISHED,
+                &*metrics::BUS_DELIVERED,
+                &*metrics::BUS_DROPPED,
+            ] {
+                let _ = metric.remove_label_values(&[topic]);
+            }
+            self.dead_letters.lock().await.remove(topic);
         }
     }
 }
@@ -103,6 +290,64 @@ impl Clone for Bus {
     fn clone(&self) -> Self {
         Self {
             subscribers: Arc::clone(&self.subscribers),
+            dead_letters: Arc::clone(&self.dead_letters),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::woduler::process::mail::data_type;
+
+    #[tokio::test]
+    async fn test_topic_stats() {
+        let mut bus = Bus::new();
+        let (sid, mut rx) = bus.subscribe("topic".to_string()).await;
+
+        for _ in 0..2 {
+            let mail = Mail {
+                typ: data_type::DATA,
+                size: 0,
+                data: Vec::new(),
+            };
+            bus.publish("topic", mail).await;
+        }
+        rx.recv().await.unwrap();
+
+        // Mails are delivered in the background, wait until both are handed out
+        let stats = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let stats = bus.describe_topic("topic").await.unwrap();
+                if stats.subscriptions[0].delivered == 2 {
+                    return stats;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("mails were not delivered in time");
+        assert_eq!(stats.published, 2);
+        assert_eq!(stats.subscriptions.len(), 1);
+        assert_eq!(stats.subscriptions[0].id, sid);
+        assert_eq!(stats.subscriptions[0].delivered, 2);
+        assert_eq!(stats.subscriptions[0].queued, 1);
+
+        let letter = DeadLetter {
+            reason: "test".to_string(),
+            topic: String::new(),
+            mail: Mail {
+                typ: data_type::DATA,
+                size: 0,
+                data: Vec::new(),
+            },
+        };
+        bus.dead_letter("topic", letter).await;
+        assert_eq!(bus.dead_letters("topic").await, 1);
+
+        bus.unsubscribe("topic", sid).await;
+        assert!(bus.describe_topic("topic").await.is_none());
+        assert_eq!(bus.dead_letters("topic").await, 0);
+        assert!(bus.topics().await.is_empty());
+    }
+}
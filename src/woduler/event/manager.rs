@@ -1,19 +1,46 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use tokio::sync::{mpsc, Mutex};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, watch, Mutex},
+    task::JoinHandle,
+};
 
-use crate::{proto::base, woduler::process::mail::Mail};
+use crate::{
+    proto::base,
+    woduler::{
+        process::mail::{DeadLetter, Envelope, Mail},
+        selector::{error::SelectorParseErr, Selector},
+    },
+};
 
+use super::balancer::{Balancer, Distribution};
 use super::bus::Bus;
 
+/// Registry holds the labels of all of the registered modules keyed by the module name
+type Registry = Arc<Mutex<HashMap<String, HashMap<String, String>>>>;
+
 #[derive(Clone)]
 pub struct Manager {
     bus: Bus,
+    modules: Registry,
+
+    // changes is notified every time the registry is modified, a receiver is kept
+    // around so that the notifications never fail
+    changes: Arc<watch::Sender<()>>,
+    changes_rx: watch::Receiver<()>,
 }
 
 impl Manager {
     pub fn new() -> Self {
-        Self { bus: Bus::new() }
+        let (changes, changes_rx) = watch::channel(());
+
+        Self {
+            bus: Bus::new(),
+            modules: Arc::new(Mutex::new(HashMap::new())),
+            changes: Arc::new(changes),
+            changes_rx,
+        }
     }
 
     /// bus returns reference to the internal bus instance
@@ -25,13 +52,55 @@ impl Manager {
     /// and will return an instance of ModuleEventBus which will
     /// provide helper functions to the caller for streaming
     /// log, data, input to the event bus
-    pub fn register_module(&self, md: &base::Module) -> ModuleEventBus {
-        ModuleEventBus::new(
+    ///
+    /// register_module will return an error if the data source selector
+    /// of the module is invalid
+    pub async fn register_module(
+        &self,
+        md: &base::Module,
+    ) -> Result<ModuleEventBus, SelectorParseErr> {
+        let inputs = Inputs {
+            selector: Manager::input_selector(md)?,
+            attribution: Manager::input_attribution(md),
+        };
+        let name = md
+            .core
+            .as_ref()
+            .map(|core| core.name.clone())
+            .unwrap_or_default();
+        let labels = md
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.labels.clone())
+            .unwrap_or_default();
+
+        self.modules.lock().await.insert(name.clone(), labels);
+        self.notify_changes();
+
+        Ok(ModuleEventBus::new(
+            name,
             Manager::create_log_topics(md),
             Manager::create_data_topics(md),
-            Manager::create_input_topics(md),
+            inputs,
             self.bus.clone(),
-        )
+            Arc::clone(&self.modules),
+            self.changes_rx.clone(),
+        ))
+    }
+
+    /// deregister_module takes in name of a module and removes it from the
+    /// list of modules which can be used as a data source
+    pub async fn deregister_module(&self, name: &str) {
+        self.modules.lock().await.remove(name);
+        self.notify_changes();
+    }
+
+    /// dead_letters takes in name of a module and returns the number of messages which
+    /// could not be delivered to it
+    pub async fn dead_letters(&self, name: &str) -> u64 {
+        self.bus
+            .dead_letters(&Manager::generate_module_topic("deadletter", name))
+            .await
     }
 
     /// generate_topic takes in type of the topic, key and value and returns
@@ -40,6 +109,59 @@ impl Manager {
         format!("{}={}.{}", key, value, typ)
     }
 
+    /// generate_module_topic takes in type of the topic and name of a module and
+    /// returns the topic which is unique to that module
+    pub fn generate_module_topic(typ: &str, name: &str) -> String {
+        Manager::generate_topic(typ, "core.hyperion.io/app", name)
+    }
+
+    /// input_selector returns the selector for the data source of the module
+    ///
+    /// A module without a data source or with an empty label selector will
+    /// not receive any input, hence `None` is returned for it
+    pub fn input_selector(md: &base::Module) -> Result<Option<Selector>, SelectorParseErr> {
+        if let Some(base::ModuleSpec {
+            data_source:
+                Some(base::module_spec::DataSource {
+                    label: Some(label_selector),
+                    ..
+                }),
+            ..
+        }) = &md.spec
+        {
+            if label_selector.selector.is_empty() && label_selector.expressions.is_empty() {
+                return Ok(None);
+            }
+
+            return Selector::from_proto(label_selector).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// input_attribution returns true if the module has opted in to receive its input
+    /// wrapped with the information about the source of the data
+    pub fn input_attribution(md: &base::Module) -> bool {
+        matches!(
+            &md.spec,
+            Some(base::ModuleSpec {
+                data_source: Some(base::module_spec::DataSource {
+                    attribution: true,
+                    ..
+                }),
+                ..
+            })
+        )
+    }
+
+    /// notify_changes wakes up all of the module event buses so that they can
+    /// rewire their inputs as per the current registry
+    fn notify_changes(&self) {
+        if self.changes.send(()).is_err() {
+            log::warn!("failed to notify registry changes");
+        }
+    }
+
     fn create_log_topics(md: &base::Module) -> Vec<String> {
         match &md.metadata {
             Some(metadata) => metadata
@@ -61,102 +183,276 @@ impl Manager {
             None => Vec::new(),
         }
     }
+}
 
-    fn create_input_topics(md: &base::Module) -> Vec<String> {
-        match &md.spec {
-            Some(spec) => {
-                if let Some(base::module_spec::DataSource {
-                    label: Some(labels_selector),
-                }) = &spec.data_source
-                {
-                    labels_selector
-                        .selector
-                        .iter()
-                        .map(|(k, v)| Manager::generate_topic("data", k, v))
-                        .collect()
-                } else {
-                    Vec::new()
-                }
-            }
-            None => Vec::new(),
-        }
-    }
+/// Inputs describes the data that a module receives from the other modules
+#[derive(Clone, Default)]
+pub struct Inputs {
+    /// selector of the modules whose data is received, `None` if only the data published
+    /// directly to the module is received
+    pub selector: Option<Selector>,
+    /// attribution wraps every received mail in an `Envelope`
+    pub attribution: bool,
 }
 
 pub struct ModuleEventBus {
-    log_topics: Option<Vec<String>>,
-    data_topics: Option<Vec<String>>,
-    input_topics: Option<Vec<String>>,
+    name: String,
+    log_topics: Vec<String>,
+    data_topics: Vec<String>,
+    inputs: Inputs,
     bus: Bus,
+    modules: Registry,
+    changes: watch::Receiver<()>,
 
-    sids: Arc<Mutex<Vec<(u128, String)>>>,
+    wiring: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+
+    // replica is the index of the replica of the module which uses the event bus, the
+    // inputs of the replicas are handed out by the balancer unless they are broadcast
+    replica: usize,
+    balancer: Option<Balancer>,
+    // _hub is the event bus which receives the inputs for the balancer, the inputs
+    // stop once the event buses of all of the replicas are dropped
+    _hub: Option<Arc<ModuleEventBus>>,
 }
 
 impl ModuleEventBus {
     pub fn new(
+        name: String,
         log_topics: Vec<String>,
         data_topics: Vec<String>,
-        input_topics: Vec<String>,
+        inputs: Inputs,
         bus: Bus,
+        modules: Registry,
+        changes: watch::Receiver<()>,
     ) -> Self {
         Self {
-            log_topics: Some(log_topics),
-            data_topics: Some(data_topics),
-            input_topics: Some(input_topics),
+            name,
+            log_topics,
+            data_topics,
+            inputs,
             bus,
+            modules,
+            changes,
+
+            wiring: None,
 
-            sids: Arc::new(Mutex::new(Vec::new())),
+            replica: 0,
+            balancer: None,
+            _hub: None,
         }
     }
 
-    pub fn stream_logs(&mut self, rx: mpsc::Receiver<Mail>) {
-        if let Some(topics) = self.log_topics.take() {
-            Self::stream(topics, rx, self.bus.clone());
+    /// replicate splits the module event bus into one for each of the replicas of the
+    /// module, the inputs are either broadcast to all of the replicas or balanced across
+    /// them as per the distribution
+    pub fn replicate(mut self, replicas: usize, distribution: Distribution) -> Vec<Self> {
+        if replicas <= 1 {
+            return vec![self];
         }
+
+        let balancer = match distribution {
+            Distribution::Broadcast => None,
+            _ => Some(Balancer::new(distribution, replicas)),
+        };
+
+        let mut buses: Vec<Self> = (0..replicas)
+            .map(|replica| Self {
+                name: self.name.clone(),
+                log_topics: self.log_topics.clone(),
+                data_topics: self.data_topics.clone(),
+                inputs: self.inputs.clone(),
+                bus: self.bus.clone(),
+                modules: Arc::clone(&self.modules),
+                changes: self.changes.clone(),
+
+                wiring: None,
+
+                replica,
+                balancer: balancer.clone(),
+                _hub: None,
+            })
+            .collect();
+
+        if let Some(balancer) = balancer {
+            let (tx, rx) = mpsc::channel(8);
+            self.recv_data(tx);
+            balancer.run(
+                rx,
+                self.bus.clone(),
+                Manager::generate_module_topic("deadletter", &self.name),
+            );
+
+            let hub = Arc::new(self);
+            for bus in buses.iter_mut() {
+                bus._hub = Some(Arc::clone(&hub));
+            }
+        }
+
+        buses
+    }
+
+    pub fn stream_logs(&mut self, rx: mpsc::Receiver<Mail>) {
+        Self::stream(self.log_topics.clone(), rx, self.bus.clone());
     }
 
     pub fn stream_data(&mut self, rx: mpsc::Receiver<Mail>) {
-        if let Some(topics) = self.data_topics.take() {
-            Self::stream(topics, rx, self.bus.clone());
-        }
+        Self::stream(self.data_topics.clone(), rx, self.bus.clone());
     }
 
+    /// recv_data subscribes to the input topic of the module and to the data topics of all of
+    /// the modules, except for this module itself, whose labels match the data source selector
+    /// and pipes the data into `tx`
+    ///
+    /// The subscriptions are recomputed every time a module is registered or deregistered
+    /// till `cleanup` is called, hence modules which start matching the selector are wired
+    /// in and the ones which stop matching are wired out without restarting the process
+    ///
+    /// The inputs of a replica whose inputs are balanced are handed to `tx` by the
+    /// balancer instead
     pub fn recv_data(&mut self, tx: mpsc::Sender<Mail>) {
-        let topics = self.input_topics.take();
+        if let Some(balancer) = &self.balancer {
+            balancer.attach(self.replica, tx);
+            return;
+        }
+
+        let selector = self.inputs.selector.clone();
+        let name = self.name.clone();
+        let attribution = self.inputs.attribution;
+        let modules = Arc::clone(&self.modules);
+        let mut changes = self.changes.clone();
         let mut bus = self.bus.clone();
-        let sids = Arc::clone(&self.sids);
+        let (stop_tx, mut stop_rx) = oneshot::channel();
 
-        tokio::spawn(async move {
-            let mut rxs = Vec::new();
+        let input_topic = Manager::generate_module_topic("input", &name);
+        let dead_letter_topic = Manager::generate_module_topic("deadletter", &name);
 
-            if let Some(topics) = topics {
-                for topic in topics.iter() {
-                    let (sid, rx) = bus.subscribe(topic.to_string()).await;
-                    sids.lock().await.push((sid, topic.to_string()));
-                    rxs.push(rx);
+        let handle = tokio::spawn(async move {
+            let mut subscriptions: HashMap<String, u128> = HashMap::new();
+
+            loop {
+                let mut topics: HashMap<String, String> = match &selector {
+                    Some(selector) => modules
+                        .lock()
+                        .await
+                        .iter()
+                        .filter(|(module, labels)| **module != name && selector.matches(labels))
+                        .map(|(module, _)| {
+                            (
+                                Manager::generate_module_topic("data", module),
+                                module.clone(),
+                            )
+                        })
+                        .collect(),
+                    None => HashMap::new(),
+                };
+
+                // Data published directly to the module doesn't originate from any module
+                topics.insert(input_topic.clone(), String::new());
+
+                log::debug!("wiring inputs of module: {} to: {:?}", name, topics);
+
+                // Unsubscribe from the modules which don't match the selector anymore
+                let stale: Vec<String> = subscriptions
+                    .keys()
+                    .filter(|topic| !topics.contains_key(*topic))
+                    .cloned()
+                    .collect();
+                for topic in stale {
+                    if let Some(sid) = subscriptions.remove(&topic) {
+                        bus.unsubscribe(&topic, sid).await;
+                    }
                 }
 
-                for mut rx in rxs {
-                    let tx = tx.clone();
+                // Subscribe to the modules which started matching the selector
+                for (topic, module) in topics {
+                    if subscriptions.contains_key(&topic) {
+                        continue;
+                    }
+
+                    let (sid, rx) = bus
+                        .subscribe_with_dead_letter(topic.clone(), Some(dead_letter_topic.clone()))
+                        .await;
+                    Self::pipe(
+                        rx,
+                        tx.clone(),
+                        topic.clone(),
+                        if attribution { Some(module) } else { None },
+                        bus.clone(),
+                        dead_letter_topic.clone(),
+                    );
+                    subscriptions.insert(topic, sid);
+                }
 
-                    tokio::spawn(async move {
-                        while let Some(mail) = rx.recv().await {
-                            if tx.send(mail).await.is_err() {
-                                log::warn!("failed to send message to the process pipe");
-                            }
+                select! {
+                    res = changes.changed() => {
+                        if res.is_err() {
+                            break;
                         }
-                    });
+                    }
+                    _ = &mut stop_rx => break,
                 }
             }
+
+            for (topic, sid) in subscriptions {
+                bus.unsubscribe(&topic, sid).await;
+            }
         });
+
+        self.wiring = Some((stop_tx, handle));
     }
 
+    /// cleanup stops rewiring the inputs and removes all of the input subscriptions
     pub async fn cleanup(&mut self) {
-        for (sid, topic) in self.sids.lock().await.iter() {
-            self.bus.unsubscribe(topic, *sid).await;
+        if let Some(balancer) = &self.balancer {
+            balancer.detach(self.replica);
         }
 
-        self.sids.lock().await.clear();
+        if let Some((stop, handle)) = self.wiring.take() {
+            // The wiring task may have already quit, hence the result can be ignored
+            let _ = stop.send(());
+
+            if let Err(err) = handle.await {
+                log::warn!("failed to cleanup input subscriptions: {}", err);
+            }
+        }
+    }
+
+    /// pipe forwards the mails coming from `rx` on `topic` into `tx`, if the source module is
+    /// given then each of the mails is wrapped in an `Envelope` attributing it to the source
+    ///
+    /// Mails which cannot be forwarded are published to the dead letter topic
+    fn pipe(
+        mut rx: mpsc::Receiver<Mail>,
+        tx: mpsc::Sender<Mail>,
+        topic: String,
+        source: Option<String>,
+        mut bus: Bus,
+        dead_letter_topic: String,
+    ) {
+        tokio::spawn(async move {
+            let mut seq = 0u64;
+
+            while let Some(mail) = rx.recv().await {
+                let mail = match &source {
+                    Some(module) => {
+                        seq += 1;
+                        Envelope::new(module.clone(), topic.clone(), seq, mail).into_mail()
+                    }
+                    None => mail,
+                };
+
+                if let Err(err) = tx.send(mail).await {
+                    log::warn!("failed to send message to the process pipe");
+
+                    let letter = DeadLetter {
+                        reason: "failed to send message to the process pipe".to_string(),
+                        topic: topic.clone(),
+                        mail: err.0,
+                    };
+                    bus.dead_letter(&dead_letter_topic, letter).await;
+                }
+            }
+        });
     }
 
     fn stream(topics: Vec<String>, mut rx: mpsc::Receiver<Mail>, mut bus: Bus) {
@@ -169,3 +465,88 @@ impl ModuleEventBus {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, labels: &[(&str, &str)], selector: &[&str]) -> base::Module {
+        base::Module {
+            core: Some(base::ModuleCore {
+                name: name.to_string(),
+            }),
+            metadata: Some(base::ModuleMetadata {
+                labels: labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            }),
+            spec: Some(base::ModuleSpec {
+                data_source: Some(base::module_spec::DataSource {
+                    label: Some(base::LabelSelector {
+                        expressions: selector.iter().map(|e| e.to_string()).collect(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            status: None,
+        }
+    }
+
+    fn mail(data: &[u8]) -> Mail {
+        Mail {
+            typ: crate::woduler::process::mail::data_type::DATA,
+            size: data.len() as u64,
+            data: data.to_vec(),
+        }
+    }
+
+    /// wait_for_subscribers waits until the topic has subscribers or not, the wiring
+    /// happens in the background
+    async fn wait_for_subscribers(bus: &Bus, topic: &str, subscribed: bool) {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while bus.describe_topic(topic).await.is_some() != subscribed {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("topic was not rewired in time");
+    }
+
+    #[tokio::test]
+    async fn test_rewiring() {
+        let mut manager = Manager::new();
+        let bus = manager.bus().clone();
+        let topic = Manager::generate_module_topic("data", "producer");
+
+        let mut consumer = manager
+            .register_module(&module("consumer", &[], &["role=producer"]))
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        consumer.recv_data(tx);
+
+        // Producer applied after the consumer started must get wired in
+        manager
+            .register_module(&module("producer", &[("role", "producer")], &[]))
+            .await
+            .unwrap();
+        wait_for_subscribers(&bus, &topic, true).await;
+
+        manager.bus().publish(&topic, mail(b"hello")).await;
+        assert_eq!(rx.recv().await.unwrap().data, b"hello".to_vec());
+
+        // Deleted producer must get wired out
+        manager.deregister_module("producer").await;
+        wait_for_subscribers(&bus, &topic, false).await;
+
+        // Nothing is handed out for a topic without subscribers
+        manager.bus().publish(&topic, mail(b"bye")).await;
+        assert!(rx.try_recv().is_err());
+
+        consumer.cleanup().await;
+    }
+}
@@ -1,5 +1,9 @@
+mod balancer;
 mod bus;
 mod manager;
+mod stats;
 
+pub use balancer::*;
 pub use bus::*;
-pub use manager::*;
\ No newline at end of file
+pub use manager::*;
+pub use stats::*;
@@ -0,0 +1,110 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Rate measures the number of events per second over fixed windows of time
+pub struct Rate {
+    window_start: Instant,
+    count: u64,
+    last: f64,
+}
+
+impl Rate {
+    pub fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            count: 0,
+            last: 0.0,
+        }
+    }
+
+    /// tick records a single event
+    pub fn tick(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            self.last = Self::per_sec(self.count, elapsed);
+            self.count = 0;
+            self.window_start = Instant::now();
+        }
+
+        self.count += 1;
+    }
+
+    /// get returns the events per second of the last complete window
+    pub fn get(&self) -> f64 {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            return Self::per_sec(self.count, elapsed);
+        }
+
+        self.last
+    }
+
+    fn per_sec(count: u64, elapsed: Duration) -> f64 {
+        // A window which hasn't seen an event in a while doesn't represent the current rate
+        if elapsed >= 2 * RATE_WINDOW {
+            return 0.0;
+        }
+
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// SubscriptionCounters are updated by the bus while delivering messages to a subscriber
+#[derive(Default)]
+pub struct SubscriptionCounters {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    pending: AtomicU64,
+}
+
+impl SubscriptionCounters {
+    /// enqueue records a message which is waiting to be handed to the subscriber
+    pub fn enqueue(&self) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// deliver records a waiting message which has been handed to the subscriber
+    pub fn deliver(&self) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// fail records a waiting message which could not be handed to the subscriber
+    pub fn fail(&self) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+/// TopicStats is a snapshot of the state of a topic on the bus
+pub struct TopicStats {
+    pub topic: String,
+    pub published: u64,
+    pub rate: f64,
+    pub subscriptions: Vec<SubscriptionStats>,
+}
+
+/// SubscriptionStats is a snapshot of the state of a subscription to a topic
+pub struct SubscriptionStats {
+    pub id: u128,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub queued: u64,
+    pub dead_letter_topic: Option<String>,
+}
@@ -1,15 +1,27 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use prost::Message;
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, watch, Mutex},
+};
 
 use crate::actor::Actor;
 use crate::proto::{api, base};
 use crate::utility;
 
+use super::dependency::{self, Dependency};
 use super::event;
-use super::process::Controller as ProcessController;
+use super::process::{
+    exec, mail, parse_signal, Controller as ProcessController, Mail, ModuleConfig, Orphans,
+    Terminal,
+};
+use super::secrets::Secrets;
+use super::selector::Selector;
+use super::validation;
 
 /// Manager is an actor and exposes the API of woduler
 /// to other parts of Hyperion
@@ -19,18 +31,38 @@ use super::process::Controller as ProcessController;
 /// between them by leveraging the Event Manager
 pub struct Manager {
     event_manager: event::Manager,
-    modules: Arc<Mutex<HashMap<String, (base::Module, ProcessController)>>>,
+    modules: dependency::Modules,
+    orphans: Orphans,
+    secrets: Secrets,
+    /// changes is notified whenever a module is applied or deleted
+    changes: Arc<watch::Sender<()>>,
 }
 
 impl Manager {
-    pub fn new() -> Self {
+    pub fn new(orphans: Orphans) -> Self {
         Self {
             event_manager: event::Manager::new(),
             modules: Arc::new(Mutex::new(HashMap::new())),
+            orphans,
+            secrets: Secrets::default(),
+            changes: Arc::new(watch::channel(()).0),
         }
     }
 
+    /// bus returns the event bus which connects the modules
+    pub fn bus(&mut self) -> event::Bus {
+        self.event_manager.bus().clone()
+    }
+
     async fn handle_apply(&mut self, mut md: base::Module, ch: oneshot::Sender<Result<String>>) {
+        // Nothing is started for a module which would only fail later on
+        if let Err(violations) = validation::validate(&md) {
+            if ch.send(Err(violations.into())).is_err() {
+                log::warn!("failed to send data to caller");
+            }
+            return;
+        }
+
         let key = utility::module_core_key(&md);
         if let Err(err) = &key {
             if ch.send(Err(anyhow::anyhow!("{}", err))).is_err() {
@@ -47,58 +79,137 @@ impl Manager {
             return;
         }
 
+        // The modules are locked until the module is stored so that no other module
+        // can close a dependency cycle in the meantime
         let mut locked = self.modules.lock().await;
-        match locked.get(&key) {
-            Some((_, controller)) => {
-                // Dumb implementation for now - No matter what, delete older version and load another
-
-                // Stop the previous controller
-                controller.stop();
-
-                // Drop the controller - hence clearing up the resources acquired by it
-                locked.remove(&key);
-
-                // Create a new process controller
-                let mut pc = ProcessController::new();
 
-                // Create new module event bus for the controller
-                let meb = self.event_manager.register_module(&md);
-
-                // Start the process controller with given module event bus
-                pc.run(&md, meb);
+        let deps = match Self::dependencies(&locked, &key, &md) {
+            Ok(deps) => deps,
+            Err(err) => {
+                if ch.send(Err(anyhow!("invalid module - {}", err))).is_err() {
+                    log::warn!("failed to send data to caller");
+                }
+                return;
+            }
+        };
 
-                // Save this controller
-                locked.insert(key.clone(), (md, pc));
+        let config = ModuleConfig::from_module(&md).and_then(|_| self.secrets.check(&md));
+        if let Err(err) = config {
+            if ch.send(Err(anyhow!("invalid module - {}", err))).is_err() {
+                log::warn!("failed to send data to caller");
             }
-            None => {
-                // Create a new process controller
-                let mut pc = ProcessController::new();
+            return;
+        }
 
-                // Create new module event bus for the controller
-                let meb = self.event_manager.register_module(&md);
+        let spec = md.spec.clone().unwrap_or_default();
+        let replicas = spec.replicas.max(1) as usize;
+        let distribution = match spec.distribution.parse::<event::Distribution>() {
+            Ok(distribution) => distribution,
+            Err(err) => {
+                if ch.send(Err(anyhow!("invalid module - {}", err))).is_err() {
+                    log::warn!("failed to send data to caller");
+                }
+                return;
+            }
+        };
+
+        // Create new module event bus for the controller
+        let meb = match self.event_manager.register_module(&md).await {
+            Ok(meb) => meb,
+            Err(err) => {
+                if ch.send(Err(anyhow!("invalid module - {}", err))).is_err() {
+                    log::warn!("failed to send data to caller");
+                }
+                return;
+            }
+        };
+
+        // The process is held back till the modules it depends on come up
+        let dependencies = dependency::gate(
+            key.clone(),
+            deps,
+            Arc::clone(&self.modules),
+            self.changes.subscribe(),
+        );
+
+        // The secret values inlined in the config are moved into the store so that only
+        // the references to them are kept along with the module, nothing can fail from
+        // here on hence no secret is stored for a module which is rejected
+        self.secrets.extract(&mut md);
+
+        // Dumb implementation for now - No matter what, delete older version and load another
+        if let Some((_, controllers)) = locked.remove(&key) {
+            // Stop the previous controllers, dropping them clears up the resources
+            // acquired by them
+            controllers.iter().for_each(ProcessController::stop);
+        }
 
-                // Start the process controller with given module event bus
+        // Create a process controller for each of the replicas and start it with the
+        // module event bus of the replica
+        let controllers = meb
+            .replicate(replicas, distribution)
+            .into_iter()
+            .enumerate()
+            .map(|(replica, meb)| {
+                let mut pc = ProcessController::new(
+                    self.orphans.clone(),
+                    self.secrets.clone(),
+                    dependencies.clone(),
+                    replica,
+                );
                 pc.run(&md, meb);
+                pc
+            })
+            .collect();
 
-                // Save this controller
-                locked.insert(key.clone(), (md, pc));
-            }
-        }
+        // Save the controllers
+        locked.insert(key.clone(), (md, controllers));
+        drop(locked);
+
+        // Let the modules which depend on this one reevaluate their dependencies
+        self.changes.send_replace(());
 
         if ch.send(Ok(format!("applied {}", key))).is_err() {
             log::warn!("failed to send data to caller");
         }
     }
 
+    /// dependencies returns the dependencies of the module which is about to be
+    /// applied, an error is returned if applying the module creates a dependency cycle
+    fn dependencies(
+        modules: &HashMap<String, (base::Module, Vec<ProcessController>)>,
+        key: &str,
+        md: &base::Module,
+    ) -> Result<Vec<Dependency>> {
+        let deps = Dependency::from_module(md)?;
+
+        let mut all: HashMap<&str, &base::Module> = modules
+            .iter()
+            .map(|(key, (md, _))| (key.as_str(), md))
+            .collect();
+        all.insert(key, md);
+
+        match dependency::find_cycle(key, &all) {
+            Some(cycle) => Err(anyhow!("dependency cycle: {}", cycle.join(" -> "))),
+            None => Ok(deps),
+        }
+    }
+
     async fn handle_delete(&mut self, md: base::ModuleCore, ch: oneshot::Sender<Result<String>>) {
         let key = md.name.clone();
 
         let mut modules = self.modules.lock().await;
 
         // Delete the module from the store
-        if let Some((_, pc)) = modules.remove(&key) {
-            // Instruct the process controller to shut down the process
-            pc.stop();
+        if let Some((_, controllers)) = modules.remove(&key) {
+            // Instruct the process controllers to shut down the processes
+            controllers.iter().for_each(ProcessController::stop);
+
+            // Stop feeding the module's data to its consumers
+            self.event_manager.deregister_module(&key).await;
+
+            // The modules which depend on this one are stopped
+            self.changes.send_replace(());
 
             if ch.send(Ok(format!("deleted {}", key))).is_err() {
                 log::warn!("failed to send data to caller")
@@ -125,22 +236,18 @@ impl Manager {
                 }
             }
             api::list_request::Filter::Label(label) => {
+                let selector = match Selector::from_proto(&label) {
+                    Ok(selector) => selector,
+                    Err(err) => {
+                        log::warn!("failed to list modules: {}", err);
+                        return;
+                    }
+                };
+
                 let modules = self.modules.lock().await;
 
                 for (_, (module, _)) in modules.iter() {
-                    let mut matches = 0;
-
-                    for (k, v) in &label.selector {
-                        if let Some(m) = &module.metadata {
-                            if let Some(val) = m.labels.get(k) {
-                                if val == v {
-                                    matches += 1;
-                                }
-                            }
-                        }
-                    }
-
-                    if matches == label.selector.len() && ch.send(module.clone()).await.is_err() {
+                    if selector.matches_module(module) && ch.send(module.clone()).await.is_err() {
                         log::warn!("failed to send data to caller")
                     }
                 }
@@ -152,10 +259,48 @@ impl Manager {
         let key = core.name;
         let modules = self.modules.lock().await;
 
-        if let Some((module, pc)) = modules.get(&key) {
+        if let Some((module, controllers)) = modules.get(&key) {
+            let mut replicas = Vec::with_capacity(controllers.len());
+            for (index, pc) in controllers.iter().enumerate() {
+                replicas.push(base::module_status::Replica {
+                    index: index as u32,
+                    msg: pc.get_status().await,
+                    usage: pc.get_usage().await.map(|usage| usage.to_proto()),
+                    runs: pc
+                        .get_runs()
+                        .await
+                        .iter()
+                        .map(|run| run.to_proto())
+                        .collect(),
+                });
+            }
+
             let mut module = module.clone();
-            module.status = Some(base::ModuleStatus {
-                msg: pc.get_status().await,
+            module.status = Some(match replicas.len() {
+                1 => {
+                    let replica = replicas.remove(0);
+                    base::ModuleStatus {
+                        msg: replica.msg,
+                        dead_letters: self.event_manager.dead_letters(&key).await,
+                        usage: replica.usage,
+                        runs: replica.runs,
+                        replicas: Vec::new(),
+                    }
+                }
+                n => {
+                    let running = controllers
+                        .iter()
+                        .filter(|pc| pc.conditions().borrow().running)
+                        .count();
+
+                    base::ModuleStatus {
+                        msg: format!("{}/{} replicas running", running, n),
+                        dead_letters: self.event_manager.dead_letters(&key).await,
+                        usage: None,
+                        runs: Vec::new(),
+                        replicas,
+                    }
+                }
             });
 
             if ch.send(Ok(module)).is_err() {
@@ -176,47 +321,376 @@ impl Manager {
         }
     }
 
-    async fn handle_watch_data(&mut self, core: base::ModuleCore, ch: mpsc::Sender<Vec<u8>>) {
-        let key = core.name;
-        let label = Self::get_module_name_label(&key);
-        let topic = event::Manager::generate_topic("data", &label.0, &label.1);
-        let mut bus = self.event_manager.bus().clone();
+    async fn handle_watch_data(&mut self, filter: command::WatchFilter, ch: mpsc::Sender<Vec<u8>>) {
+        let topics = self.watch_topics("data", filter).await;
+        self.watch(topics, ch);
+    }
 
-        let (sid, mut recv) = bus.subscribe(topic.clone()).await;
+    async fn handle_watch_dead_letter(
+        &mut self,
+        filter: command::WatchFilter,
+        ch: mpsc::Sender<Vec<u8>>,
+    ) {
+        let topics = self.watch_topics("deadletter", filter).await;
+        self.watch(topics, ch);
+    }
 
-        tokio::spawn(async move {
-            // Keep listening to the data coming from the bus
-            while let Some(data) = recv.recv().await {
-                if ch.send(data.data).await.is_err() {
-                    log::warn!("failed to send data to the caller - will closing subscription");
-                    break;
+    async fn handle_watch_log(&mut self, filter: command::WatchFilter, ch: mpsc::Sender<Vec<u8>>) {
+        let topics = self.watch_topics("log", filter).await;
+        self.watch(topics, ch);
+    }
+
+    /// watch_topics returns the topics of type `typ` of all of the modules selected
+    /// by the filter
+    async fn watch_topics(&self, typ: &str, filter: command::WatchFilter) -> Vec<String> {
+        match filter {
+            command::WatchFilter::Core(core) => {
+                vec![event::Manager::generate_module_topic(typ, &core.name)]
+            }
+            command::WatchFilter::Label(label) => {
+                let selector = match Selector::from_proto(&label) {
+                    Ok(selector) => selector,
+                    Err(err) => {
+                        log::warn!("failed to watch modules: {}", err);
+                        return Vec::new();
+                    }
+                };
+
+                self.modules
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, (module, _))| selector.matches_module(module))
+                    .map(|(key, _)| event::Manager::generate_module_topic(typ, key))
+                    .collect()
+            }
+        }
+    }
+
+    /// watch subscribes to all of the given topics and pipes the data coming from
+    /// them into `ch` till the caller stops listening
+    fn watch(&mut self, topics: Vec<String>, ch: mpsc::Sender<Vec<u8>>) {
+        for topic in topics {
+            let mut bus = self.event_manager.bus().clone();
+            let ch = ch.clone();
+
+            tokio::spawn(async move {
+                let (sid, mut recv) = bus.subscribe(topic.clone()).await;
+
+                // Keep listening to the data coming from the bus
+                loop {
+                    select! {
+                        data = recv.recv() => match data {
+                            Some(data) => {
+                                if ch.send(data.data).await.is_err() {
+                                    log::warn!("failed to send data to the caller - will closing subscription");
+                                    break;
+                                }
+                            }
+                            None => break,
+                        },
+                        _ = ch.closed() => break,
+                    }
                 }
+
+                // Close the subscription
+                bus.unsubscribe(&topic, sid).await;
+            });
+        }
+    }
+
+    async fn handle_publish(
+        &mut self,
+        mut rx: mpsc::Receiver<api::PublishRequest>,
+        ch: oneshot::Sender<u64>,
+    ) {
+        let mut bus = self.event_manager.bus().clone();
+
+        tokio::spawn(async move {
+            let mut published = 0;
+
+            while let Some(req) = rx.recv().await {
+                let topic = match req.target {
+                    Some(api::publish_request::Target::Core(core)) => {
+                        event::Manager::generate_module_topic("input", &core.name)
+                    }
+                    Some(api::publish_request::Target::Topic(topic)) => topic,
+                    None => continue,
+                };
+
+                let mail = Mail {
+                    typ: mail::data_type::DATA,
+                    size: req.data.len() as u64,
+                    data: req.data,
+                };
+                bus.publish(&topic, mail).await;
+                published += 1;
             }
 
-            // Close the subscription
-            bus.unsubscribe(&topic, sid).await;
+            if ch.send(published).is_err() {
+                log::warn!("failed to send data to the caller");
+            }
         });
     }
 
-    async fn handle_watch_log(&mut self, core: base::ModuleCore, ch: mpsc::Sender<Vec<u8>>) {
+    async fn handle_list_topics(&mut self, ch: oneshot::Sender<Vec<api::TopicInfo>>) {
+        let topics = self
+            .event_manager
+            .bus()
+            .topics()
+            .await
+            .into_iter()
+            .map(|stats| Self::topic_info(stats, false))
+            .collect();
+
+        if ch.send(topics).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    async fn handle_describe_topic(
+        &mut self,
+        topic: String,
+        ch: oneshot::Sender<Result<api::TopicInfo>>,
+    ) {
+        let res = match self.event_manager.bus().describe_topic(&topic).await {
+            Some(stats) => Ok(Self::topic_info(stats, true)),
+            None => Err(anyhow!("topic \"{}\" not found", topic)),
+        };
+
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    async fn handle_attach(
+        &self,
+        core: base::ModuleCore,
+        replica: u32,
+        ch: oneshot::Sender<Result<Terminal>>,
+    ) {
         let key = core.name;
-        let topic = event::Manager::generate_topic("log", "core.hyperion.io/app", &key);
-        let mut bus = self.event_manager.bus().clone();
 
-        let (sid, mut recv) = bus.subscribe(topic.clone()).await;
+        let res = match self.modules.lock().await.get(&key) {
+            Some((module, controllers)) => match Self::replica(&key, controllers, replica) {
+                Ok(pc) => match pc.get_terminal().await {
+                    Some(terminal) => Ok(terminal),
+                    None if module.spec.as_ref().map(|spec| spec.tty) == Some(true) => {
+                        Err(anyhow!("module \"{}\" is not running", key))
+                    }
+                    None => Err(anyhow!("module \"{}\" does not run on a terminal", key)),
+                },
+                Err(err) => Err(err),
+            },
+            None => Err(anyhow!("module with key \"{}\" not found", key)),
+        };
+
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    async fn handle_exec(
+        &self,
+        core: base::ModuleCore,
+        replica: u32,
+        command: Vec<String>,
+        ch: oneshot::Sender<Result<mpsc::Receiver<exec::Output>>>,
+    ) {
+        let key = core.name;
 
-        tokio::spawn(async move {
-            // Keep listening to the data coming from the bus
-            while let Some(data) = recv.recv().await {
-                if ch.send(data.data).await.is_err() {
-                    log::warn!("failed to send data to the caller - will closing subscription");
-                    break;
+        let res = match self.modules.lock().await.get(&key) {
+            Some((_, controllers)) => match Self::replica(&key, controllers, replica) {
+                Ok(pc) => {
+                    log::info!(
+                        "executing {:?} in the context of replica {} of module \"{}\"",
+                        command,
+                        replica,
+                        key
+                    );
+
+                    pc.exec(&command)
+                        .await
+                        .map_err(|err| anyhow!("failed to execute in module \"{}\" - {}", key, err))
+                }
+                Err(err) => Err(err),
+            },
+            None => Err(anyhow!("module with key \"{}\" not found", key)),
+        };
+
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    async fn handle_signal(
+        &mut self,
+        core: base::ModuleCore,
+        sig: String,
+        ch: oneshot::Sender<Result<String>>,
+    ) {
+        let key = core.name;
+
+        let res = match parse_signal(&sig) {
+            Ok(sig) => match self.modules.lock().await.get(&key) {
+                Some((_, controllers)) => {
+                    // Every replica which is running is signalled
+                    let mut res = Err(anyhow!("process is not running"));
+                    for pc in controllers {
+                        let sent = pc.signal(sig).await;
+                        if res.is_err() {
+                            res = sent.map(|_| sig);
+                        }
+                    }
+
+                    res.map_err(|err| anyhow!("failed to signal module \"{}\" - {}", key, err))
                 }
+                None => Err(anyhow!("module with key \"{}\" not found", key)),
+            },
+            Err(err) => Err(err),
+        };
+
+        let res = match res {
+            Ok(sig) => {
+                self.audit(&key, "signal", sig.as_str().to_string()).await;
+                Ok(format!("sent {} to {}", sig, key))
             }
+            Err(err) => Err(err),
+        };
 
-            // Close the subscription
-            bus.unsubscribe(&topic, sid).await;
-        });
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    /// handle_lifecycle performs the `action` on the process of the module using `op`
+    async fn handle_lifecycle(
+        &mut self,
+        core: base::ModuleCore,
+        action: &str,
+        op: fn(&ProcessController) -> Result<()>,
+        ch: oneshot::Sender<Result<String>>,
+    ) {
+        let key = core.name;
+
+        let res = match self.modules.lock().await.get(&key) {
+            Some((_, controllers)) => controllers
+                .iter()
+                .try_for_each(op)
+                .map_err(|err| anyhow!("failed to {} module \"{}\" - {}", action, key, err)),
+            None => Err(anyhow!("module with key \"{}\" not found", key)),
+        };
+
+        let res = match res {
+            Ok(()) => {
+                self.audit(&key, action, String::new()).await;
+                Ok(format!("{} {}", action, key))
+            }
+            Err(err) => Err(err),
+        };
+
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    /// handle_secrets stores the secrets in `set` and removes the ones in `delete`, the
+    /// names of the stored secrets are sent back
+    ///
+    /// The processes pick up the changes the next time they are started
+    async fn handle_secrets(
+        &mut self,
+        set: HashMap<String, Vec<u8>>,
+        delete: Vec<String>,
+        ch: oneshot::Sender<Result<Vec<String>>>,
+    ) {
+        let res = set
+            .into_iter()
+            .try_for_each(|(name, value)| {
+                log::info!("setting secret \"{}\"", name);
+                self.secrets.set(&name, value)
+            })
+            .map(|()| {
+                delete.iter().for_each(|name| {
+                    log::info!("deleting secret \"{}\"", name);
+                    self.secrets.delete(name)
+                });
+                self.secrets.names()
+            });
+
+        if ch.send(res).is_err() {
+            log::warn!("failed to send data to the caller");
+        }
+    }
+
+    /// replica returns the process controller of the replica of the module
+    fn replica<'a>(
+        key: &str,
+        controllers: &'a [ProcessController],
+        replica: u32,
+    ) -> Result<&'a ProcessController> {
+        controllers
+            .get(replica as usize)
+            .ok_or_else(|| anyhow!("module \"{}\" has no replica {}", key, replica))
+    }
+
+    /// audit records an action taken on the module by an operator, the event is
+    /// published on the audit topic of the module
+    async fn audit(&mut self, module: &str, action: &str, detail: String) {
+        log::info!("audit: {} on module \"{}\" - {}", action, module, detail);
+
+        let event = base::AuditEvent {
+            action: action.to_string(),
+            module: module.to_string(),
+            detail,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs() as i64)
+                .unwrap_or_default(),
+        };
+        let data = event.encode_to_vec();
+
+        let mail = Mail {
+            typ: mail::data_type::DATA,
+            size: data.len() as u64,
+            data,
+        };
+        self.event_manager
+            .bus()
+            .publish(
+                &event::Manager::generate_module_topic("audit", module),
+                mail,
+            )
+            .await;
+    }
+
+    /// topic_info converts the snapshot of a topic into its API representation, the
+    /// subscriptions are included only if `detailed` is true
+    fn topic_info(stats: event::TopicStats, detailed: bool) -> api::TopicInfo {
+        let subscribers = stats.subscriptions.len() as u32;
+        let subscriptions = if detailed {
+            stats
+                .subscriptions
+                .into_iter()
+                .map(|sub| api::SubscriptionInfo {
+                    id: sub.id.to_string(),
+                    delivered: sub.delivered,
+                    dropped: sub.dropped,
+                    queued: sub.queued,
+                    dead_letter_topic: sub.dead_letter_topic.unwrap_or_default(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        api::TopicInfo {
+            topic: stats.topic,
+            published: stats.published,
+            rate: stats.rate,
+            subscribers,
+            subscriptions,
+        }
     }
 
     fn setup_defaults(md: &mut base::Module, name: String) -> Result<()> {
@@ -246,6 +720,9 @@ impl Clone for Manager {
         Self {
             event_manager: self.event_manager.clone(),
             modules: Arc::clone(&self.modules),
+            orphans: self.orphans.clone(),
+            secrets: self.secrets.clone(),
+            changes: Arc::clone(&self.changes),
         }
     }
 }
@@ -259,7 +736,7 @@ impl Actor for Manager {
         tokio::spawn(async move {
             match msg {
                 command::Command::Apply(md, res) => {
-                    m.handle_apply(md, res).await;
+                    m.handle_apply(*md, res).await;
                 }
                 command::Command::Delete(md, res) => {
                     m.handle_delete(md, res).await;
@@ -276,6 +753,42 @@ impl Actor for Manager {
                 command::Command::WatchLog(core, res) => {
                     m.handle_watch_log(core, res).await;
                 }
+                command::Command::WatchDeadLetter(filter, res) => {
+                    m.handle_watch_dead_letter(filter, res).await;
+                }
+                command::Command::Publish(rx, res) => {
+                    m.handle_publish(rx, res).await;
+                }
+                command::Command::ListTopics(res) => {
+                    m.handle_list_topics(res).await;
+                }
+                command::Command::DescribeTopic(topic, res) => {
+                    m.handle_describe_topic(topic, res).await;
+                }
+                command::Command::Attach(core, replica, res) => {
+                    m.handle_attach(core, replica, res).await;
+                }
+                command::Command::Exec(core, replica, cmd, res) => {
+                    m.handle_exec(core, replica, cmd, res).await;
+                }
+                command::Command::Signal(core, sig, res) => {
+                    m.handle_signal(core, sig, res).await;
+                }
+                command::Command::Restart(core, res) => {
+                    m.handle_lifecycle(core, "restart", ProcessController::restart, res)
+                        .await;
+                }
+                command::Command::Pause(core, res) => {
+                    m.handle_lifecycle(core, "pause", ProcessController::pause, res)
+                        .await;
+                }
+                command::Command::Resume(core, res) => {
+                    m.handle_lifecycle(core, "resume", ProcessController::resume, res)
+                        .await;
+                }
+                command::Command::Secrets(set, delete, res) => {
+                    m.handle_secrets(set, delete, res).await;
+                }
             }
         });
     }
@@ -285,7 +798,10 @@ pub mod command {
     use tokio::sync::{mpsc, oneshot};
 
     pub enum Command {
-        Apply(super::base::Module, oneshot::Sender<anyhow::Result<String>>),
+        Apply(
+            Box<super::base::Module>,
+            oneshot::Sender<anyhow::Result<String>>,
+        ),
         Delete(
             super::base::ModuleCore,
             oneshot::Sender<anyhow::Result<String>>,
@@ -298,7 +814,57 @@ pub mod command {
             super::base::ModuleCore,
             oneshot::Sender<anyhow::Result<super::base::Module>>,
         ),
-        WatchData(super::base::ModuleCore, mpsc::Sender<Vec<u8>>),
-        WatchLog(super::base::ModuleCore, mpsc::Sender<Vec<u8>>),
+        WatchData(WatchFilter, mpsc::Sender<Vec<u8>>),
+        WatchLog(WatchFilter, mpsc::Sender<Vec<u8>>),
+        WatchDeadLetter(WatchFilter, mpsc::Sender<Vec<u8>>),
+        Publish(
+            mpsc::Receiver<super::api::PublishRequest>,
+            oneshot::Sender<u64>,
+        ),
+        ListTopics(oneshot::Sender<Vec<super::api::TopicInfo>>),
+        DescribeTopic(
+            String,
+            oneshot::Sender<anyhow::Result<super::api::TopicInfo>>,
+        ),
+        Attach(
+            super::base::ModuleCore,
+            u32,
+            oneshot::Sender<anyhow::Result<super::Terminal>>,
+        ),
+        Exec(
+            super::base::ModuleCore,
+            u32,
+            Vec<String>,
+            oneshot::Sender<anyhow::Result<mpsc::Receiver<super::exec::Output>>>,
+        ),
+        Signal(
+            super::base::ModuleCore,
+            String,
+            oneshot::Sender<anyhow::Result<String>>,
+        ),
+        Restart(
+            super::base::ModuleCore,
+            oneshot::Sender<anyhow::Result<String>>,
+        ),
+        Pause(
+            super::base::ModuleCore,
+            oneshot::Sender<anyhow::Result<String>>,
+        ),
+        Resume(
+            super::base::ModuleCore,
+            oneshot::Sender<anyhow::Result<String>>,
+        ),
+        Secrets(
+            std::collections::HashMap<String, Vec<u8>>,
+            Vec<String>,
+            oneshot::Sender<anyhow::Result<Vec<String>>>,
+        ),
+    }
+
+    /// WatchFilter selects the modules whose streams are watched, either a single
+    /// module by its core or all of the modules matching a label selector
+    pub enum WatchFilter {
+        Core(super::base::ModuleCore),
+        Label(super::base::LabelSelector),
     }
 }
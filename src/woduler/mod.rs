@@ -1,3 +1,7 @@
-mod event;
+pub mod dependency;
+pub mod event;
 pub mod manager;
 pub mod process;
+pub mod secrets;
+pub mod selector;
+pub mod validation;
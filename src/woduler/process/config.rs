@@ -0,0 +1,336 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{self, DirBuilder, OpenOptions},
+    io::Write,
+    os::unix::{
+        ffi::OsStringExt,
+        fs::{DirBuilderExt, OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use nix::{
+    mount::{mount, umount2, MntFlags, MsFlags},
+    unistd,
+};
+
+use super::Security;
+use crate::{proto::base, woduler::secrets::Secrets};
+
+const CONFIG_ROOT: &str = "/run/hyperion/config";
+/// CONFIG_DIR_ENV is set to the path of the config directory of the process
+const CONFIG_DIR_ENV: &str = "HYPERION_CONFIG_DIR";
+
+/// Source is where the value of a config entry comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Data(Vec<u8>),
+    /// Secret is the name of a secret stored in hyperion, it is looked up every time
+    /// the process is started
+    Secret(String),
+}
+
+/// Target is how the value of a config entry is handed to the process
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Target {
+    /// File is the name of a file in the config directory of the process
+    File(String),
+    /// Env is the name of an environment variable of the process
+    Env(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigEntry {
+    pub target: Target,
+    pub source: Source,
+}
+
+/// ModuleConfig is the config of a module which is materialized for its process
+#[derive(Clone, Debug, Default)]
+pub struct ModuleConfig {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl ModuleConfig {
+    /// from_module returns the config defined in the spec of the module
+    pub fn from_module(md: &base::Module) -> Result<Self> {
+        let entries = match &md.spec {
+            Some(spec) => &spec.config,
+            None => return Ok(Self::default()),
+        };
+
+        let mut seen = HashSet::new();
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                let target = match entry.env {
+                    true if Self::is_env_name(&entry.name) => Target::Env(entry.name.clone()),
+                    false if Self::is_file_name(&entry.name) => Target::File(entry.name.clone()),
+                    _ => return Err(anyhow!("invalid config entry name \"{}\"", entry.name)),
+                };
+                if !seen.insert(target.clone()) {
+                    return Err(anyhow!("duplicate config entry \"{}\"", entry.name));
+                }
+
+                let source = match entry.secret.is_empty() {
+                    true => Source::Data(entry.data.clone()),
+                    false => Source::Secret(entry.secret.clone()),
+                };
+
+                Ok(ConfigEntry { target, source })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { entries })
+    }
+
+    /// materialize writes the files of the config into a private directory of the
+    /// process with the given name and resolves its environment variables, the
+    /// directory is removed once the returned value is dropped
+    ///
+    /// The name must be unique to the process, the process finds the directory through
+    /// the `HYPERION_CONFIG_DIR` environment variable
+    pub fn materialize(
+        &self,
+        name: &str,
+        secrets: &Secrets,
+        security: &Security,
+    ) -> Result<Materialized> {
+        self.materialize_in(Path::new(CONFIG_ROOT), name, secrets, security)
+    }
+
+    fn materialize_in(
+        &self,
+        root: &Path,
+        name: &str,
+        secrets: &Secrets,
+        security: &Security,
+    ) -> Result<Materialized> {
+        let mut materialized = Materialized::default();
+
+        for entry in self.entries.iter() {
+            let value = match &entry.source {
+                Source::Data(data) => data.clone(),
+                Source::Secret(secret) => secrets.get(secret)?,
+            };
+
+            match &entry.target {
+                Target::Env(key) => materialized
+                    .env
+                    .push((key.clone(), OsString::from_vec(value))),
+                Target::File(file) => {
+                    if materialized.dir.is_none() {
+                        materialized.dir = Some(ConfigDir::create(&root.join(name), security)?);
+                    }
+
+                    let dir = materialized.dir.as_ref().unwrap();
+                    dir.write(file, &value, security)
+                        .map_err(|err| anyhow!("failed to write config file {} - {}", file, err))?;
+                }
+            }
+        }
+
+        Ok(materialized)
+    }
+
+    fn is_env_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn is_file_name(name: &str) -> bool {
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+    }
+}
+
+/// Materialized is the config of a module as handed to its process
+#[derive(Default)]
+pub struct Materialized {
+    dir: Option<ConfigDir>,
+    env: Vec<(String, OsString)>,
+}
+
+impl Materialized {
+    /// env returns the environment variables of the process, including the path to the
+    /// config directory if there is one
+    pub fn env(&self) -> Vec<(String, OsString)> {
+        let mut env = self.env.clone();
+        if let Some(dir) = &self.dir {
+            env.push((
+                CONFIG_DIR_ENV.to_string(),
+                dir.path.clone().into_os_string(),
+            ));
+        }
+
+        env
+    }
+}
+
+/// ConfigDir is a directory only accessible by the process, backed by a tmpfs where
+/// possible so that the files never reach the disk
+struct ConfigDir {
+    path: PathBuf,
+    mounted: bool,
+}
+
+impl ConfigDir {
+    /// create creates the directory at the path, the path has to be unique to the
+    /// process as the directory is removed along with whatever is in it once dropped
+    fn create(path: &Path, security: &Security) -> Result<Self> {
+        if let Some(root) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(root)
+                .map_err(|err| anyhow!("failed to create config directory - {}", err))?;
+        }
+
+        // The directory of another process is never taken over
+        DirBuilder::new()
+            .mode(0o700)
+            .create(path)
+            .map_err(|err| anyhow!("failed to create config directory - {}", err))?;
+
+        let mounted = match mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            Some("mode=0700"),
+        ) {
+            Ok(()) => true,
+            Err(err) => {
+                log::warn!(
+                    "failed to mount tmpfs at {} - falling back to a plain directory: {}",
+                    path.display(),
+                    err
+                );
+                false
+            }
+        };
+
+        let dir = Self {
+            path: path.to_path_buf(),
+            mounted,
+        };
+        unistd::chown(path, security.uid, security.gid)?;
+
+        Ok(dir)
+    }
+
+    fn write(&self, name: &str, value: &[u8], security: &Security) -> Result<()> {
+        let path = self.path.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?
+            .write_all(value)?;
+        unistd::chown(&path, security.uid, security.gid)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for ConfigDir {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(err) = umount2(&self.path, MntFlags::MNT_DETACH) {
+                log::warn!("failed to unmount {}: {}", self.path.display(), err);
+            }
+        }
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            log::warn!("failed to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, data: &str, secret: &str, env: bool) -> base::module_spec::ConfigEntry {
+        base::module_spec::ConfigEntry {
+            name: name.to_string(),
+            data: data.as_bytes().to_vec(),
+            secret: secret.to_string(),
+            env,
+        }
+    }
+
+    fn module(entries: Vec<base::module_spec::ConfigEntry>) -> base::Module {
+        base::Module {
+            spec: Some(base::ModuleSpec {
+                config: entries,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_module() {
+        let config = ModuleConfig::from_module(&module(vec![
+            entry("app.toml", "level = 1", "", false),
+            entry("API_TOKEN", "", "token", true),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.entries[1],
+            ConfigEntry {
+                target: Target::Env("API_TOKEN".to_string()),
+                source: Source::Secret("token".to_string()),
+            }
+        );
+
+        for invalid in [
+            vec![entry("../escape", "", "", false)],
+            vec![entry("", "", "", false)],
+            vec![entry("1TOKEN", "", "", true)],
+            vec![entry("a", "", "", false), entry("a", "", "", false)],
+        ] {
+            assert!(ModuleConfig::from_module(&module(invalid)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_materialize() {
+        let root = std::env::temp_dir().join(format!("hyperion-config-{}", std::process::id()));
+        let secrets = Secrets::default();
+        let config = ModuleConfig::from_module(&module(vec![
+            entry("app.toml", "level = 1", "", false),
+            entry("API_TOKEN", "", "token", true),
+        ]))
+        .unwrap();
+
+        // The secrets are looked up when the config is materialized
+        let security = Security::default();
+        assert!(config
+            .materialize_in(&root, "app", &secrets, &security)
+            .is_err());
+        secrets.set("token", b"hunter2".to_vec()).unwrap();
+
+        let materialized = config
+            .materialize_in(&root, "app", &secrets, &security)
+            .unwrap();
+        let dir = root.join("app");
+        assert_eq!(fs::read(dir.join("app.toml")).unwrap(), b"level = 1");
+
+        // The directory of another process is left alone
+        assert!(config
+            .materialize_in(&root, "app", &secrets, &security)
+            .is_err());
+        assert!(dir.join("app.toml").exists());
+
+        let env = materialized.env();
+        assert!(env.contains(&("API_TOKEN".to_string(), "hunter2".into())));
+        assert!(env.contains(&(CONFIG_DIR_ENV.to_string(), dir.clone().into_os_string())));
+
+        drop(materialized);
+        assert!(!dir.exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
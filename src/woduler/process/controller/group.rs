@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc;
+
+use super::{Mail, Options, Process};
+use crate::proto::base;
+
+/// Auxiliary is a process which runs along the main process of a module
+#[derive(Clone, Debug)]
+pub struct Auxiliary {
+    pub name: String,
+    pub command: Vec<String>,
+}
+
+impl Auxiliary {
+    fn from_proto(kind: &str, aux: &base::module_spec::Auxiliary) -> Result<Self> {
+        let program = aux
+            .command
+            .first()
+            .ok_or_else(|| anyhow!("{} process \"{}\" has no command", kind, aux.name))?;
+
+        Ok(Self {
+            name: match aux.name.is_empty() {
+                true => program.clone(),
+                false => aux.name.clone(),
+            },
+            command: aux.command.clone(),
+        })
+    }
+
+    /// spawn starts the process with the same options as the main process, its output
+    /// is sent to `logs` and it gets no input
+    ///
+    /// The sandbox is set up anew for the process hence it does not share the
+    /// namespaces of the main process, which is not running yet
+    fn spawn(&self, opts: &Options, logs: &mpsc::Sender<Mail>) -> Result<Process> {
+        let opts = Options {
+            tty: false,
+            logs: true,
+            ..opts.clone()
+        };
+
+        Process::spawn(&self.command, logs.clone(), mpsc::channel(1).1, opts)
+    }
+}
+
+/// Group holds the processes which share the lifecycle of the main process of a module
+#[derive(Clone, Debug, Default)]
+pub struct Group {
+    /// init processes run to completion, one after the other, before the main process
+    /// is started
+    pub init: Vec<Auxiliary>,
+    /// sidecars are started before the main process and stopped once it exits
+    pub sidecars: Vec<Auxiliary>,
+}
+
+impl Group {
+    /// from_module takes in a module definition and returns its group of processes
+    pub fn from_module(md: &base::Module) -> Result<Self> {
+        let spec = match &md.spec {
+            Some(spec) => spec,
+            None => return Ok(Self::default()),
+        };
+
+        Ok(Self {
+            init: spec
+                .init
+                .iter()
+                .map(|aux| Auxiliary::from_proto("init", aux))
+                .collect::<Result<_>>()?,
+            sidecars: spec
+                .sidecars
+                .iter()
+                .map(|aux| Auxiliary::from_proto("sidecar", aux))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// run_init runs the init processes in order and returns an error as soon as one
+    /// of them fails
+    pub async fn run_init(&self, opts: &Options, logs: &mpsc::Sender<Mail>) -> Result<()> {
+        for init in self.init.iter() {
+            log::debug!("running init process: {}", init.name);
+
+            let mut process = init.spawn(opts, logs)?;
+            let status = process.wait_on_child().await;
+            // Nothing started by an init process outlives it
+            process.reap_tree(None).await?;
+
+            let status = status?;
+            if !status.success() {
+                return Err(anyhow!(
+                    "init process \"{}\" failed - {}",
+                    init.name,
+                    status
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// start_sidecars starts the sidecar processes, the ones already started are killed
+    /// if one of them fails to start
+    pub fn start_sidecars(
+        &self,
+        opts: &Options,
+        logs: &mpsc::Sender<Mail>,
+    ) -> Result<Vec<Process>> {
+        self.sidecars
+            .iter()
+            .map(|sidecar| {
+                log::debug!("starting sidecar process: {}", sidecar.name);
+
+                sidecar.spawn(opts, logs).map_err(|err| {
+                    anyhow!(
+                        "failed to start sidecar process \"{}\" - {}",
+                        sidecar.name,
+                        err
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// stop_sidecars terminates the sidecar processes along with their descendants
+    pub async fn stop_sidecars(sidecars: Vec<Process>) {
+        for mut sidecar in sidecars {
+            if let Err(err) = sidecar.terminate().await {
+                log::warn!("failed to stop sidecar process: {}", err);
+            }
+            if let Err(err) = sidecar.reap_tree(None).await {
+                log::warn!("failed to reap sidecar process: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aux(name: &str, script: &str) -> base::module_spec::Auxiliary {
+        base::module_spec::Auxiliary {
+            name: name.to_string(),
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+        }
+    }
+
+    fn group(
+        init: Vec<base::module_spec::Auxiliary>,
+        sidecars: Vec<base::module_spec::Auxiliary>,
+    ) -> Group {
+        Group::from_module(&base::Module {
+            spec: Some(base::ModuleSpec {
+                init,
+                sidecars,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_init() {
+        let (logs_tx, mut logs_rx) = mpsc::channel(8);
+        let opts = Options::default();
+
+        let ok = group(vec![aux("first", "echo one"), aux("", "echo two")], vec![]);
+        assert_eq!(ok.init[1].name, "sh");
+        ok.run_init(&opts, &logs_tx).await.unwrap();
+        assert_eq!(logs_rx.recv().await.unwrap().data, b"one");
+        assert_eq!(logs_rx.recv().await.unwrap().data, b"two");
+
+        let failing = group(
+            vec![aux("fetch", "exit 3"), aux("never", "echo never")],
+            vec![],
+        );
+        let err = failing.run_init(&opts, &logs_tx).await.unwrap_err();
+        assert!(err.to_string().contains("\"fetch\""));
+        drop(logs_tx);
+        assert!(logs_rx.recv().await.is_none());
+
+        assert!(Group::from_module(&base::Module {
+            spec: Some(base::ModuleSpec {
+                sidecars: vec![base::module_spec::Auxiliary::default()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sidecars() {
+        let (logs_tx, mut logs_rx) = mpsc::channel(8);
+
+        let group = group(vec![], vec![aux("watch", "echo up; exec sleep 30")]);
+        let sidecars = group.start_sidecars(&Options::default(), &logs_tx).unwrap();
+        assert_eq!(logs_rx.recv().await.unwrap().data, b"up");
+
+        let stopped = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            Group::stop_sidecars(sidecars),
+        );
+        assert!(stopped.await.is_ok());
+    }
+}
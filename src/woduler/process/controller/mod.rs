@@ -1,28 +1,116 @@
+mod group;
+mod schedule;
 mod state;
 
-use std::{env, io::Cursor, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    io::Cursor,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
+use nix::sys::signal::Signal;
 use tokio::{
     select,
-    sync::{mpsc, Mutex, Notify},
+    sync::{mpsc, watch, Mutex, Notify},
+    task::{JoinHandle, JoinSet},
 };
 use uuid::Uuid;
 
-use super::{mail, Mail, Process};
-use crate::{proto::base, woduler::event::ModuleEventBus};
+use super::{
+    exec, mail, pidfd::PidFd, Cgroup, Limits, Mail, ModuleConfig, Options, Orphans, Process,
+    Sandbox, Security, Terminal, Usage,
+};
+use crate::{
+    metrics,
+    proto::base,
+    utility,
+    woduler::{event::ModuleEventBus, secrets::Secrets},
+};
+pub use group::Group;
+pub use schedule::Schedule;
+use schedule::{History, Outcome, Policy, Run};
 use state::*;
 
+const USAGE_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Running holds the handles to the running process which are used to interact with it
+#[derive(Clone)]
+struct Running {
+    pidfd: Arc<PidFd>,
+    terminal: Option<Terminal>,
+    context: exec::Context,
+}
+
 pub struct Controller {
     process_state: Arc<Mutex<ProcessState>>,
+    /// usage and running are keyed by the id of the run as the runs of a scheduled
+    /// module might overlap, the process of any other module is run 0
+    usage: Arc<Mutex<BTreeMap<u64, Usage>>>,
+    running: Arc<Mutex<BTreeMap<u64, Running>>>,
     cancel: Arc<Notify>,
+    restart: Arc<Notify>,
+    paused: Arc<watch::Sender<bool>>,
+    history: Arc<Mutex<History>>,
+    dependencies: watch::Receiver<bool>,
+    conditions: Arc<watch::Sender<Conditions>>,
+    replica: usize,
+    orphans: Orphans,
+    secrets: Secrets,
+}
+
+/// Conditions tell the modules which depend on a module how far its process got
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Conditions {
+    pub running: bool,
+    /// ready is set once the running process writes to its stdout
+    pub ready: bool,
+    /// completed is set once the process exits successfully
+    pub completed: bool,
+}
+
+/// Job holds what a single run of a scheduled module needs
+struct Job {
+    id: u64,
+    md: base::Module,
+    name: String,
+    stdout: mpsc::Sender<Mail>,
+    stdin: mpsc::Receiver<Mail>,
+    logs: mpsc::Sender<Mail>,
+    deadline: Option<Duration>,
+    stop: Arc<Notify>,
+    usage: Arc<Mutex<BTreeMap<u64, Usage>>>,
+    running: Arc<Mutex<BTreeMap<u64, Running>>>,
+    orphans: Orphans,
+    secrets: Secrets,
 }
 
 impl Controller {
-    pub fn new() -> Self {
+    /// new returns a process controller for the given replica of a module which starts
+    /// the process only while `dependencies` is set, the secrets which the config of the
+    /// module refers to are looked up in `secrets`
+    pub fn new(
+        orphans: Orphans,
+        secrets: Secrets,
+        dependencies: watch::Receiver<bool>,
+        replica: usize,
+    ) -> Self {
         Self {
             process_state: Arc::new(Mutex::new(ProcessState::new())),
+            usage: Arc::new(Mutex::new(BTreeMap::new())),
+            running: Arc::new(Mutex::new(BTreeMap::new())),
             cancel: Arc::new(Notify::new()),
+            restart: Arc::new(Notify::new()),
+            paused: Arc::new(watch::channel(false).0),
+            history: Arc::new(Mutex::new(History::default())),
+            dependencies,
+            conditions: Arc::new(watch::channel(Conditions::default()).0),
+            replica,
+            orphans,
+            secrets,
         }
     }
 
@@ -31,118 +119,924 @@ impl Controller {
     ///
     /// Module Event Bus is used to connect process streams to the main event bus
     pub fn run(&mut self, md: &base::Module, mut eb: ModuleEventBus) {
+        match Schedule::from_module(md) {
+            Ok(Some(schedule)) => return self.run_scheduled(md, eb, schedule),
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("failed to parse schedule: {}", err);
+
+                let state = Arc::clone(&self.process_state);
+                tokio::spawn(async move { state.lock().await.set(State::Error(err.to_string())) });
+                return;
+            }
+        }
+
         let state = Arc::clone(&self.process_state);
+        let usage = Arc::clone(&self.usage);
+        let running = Arc::clone(&self.running);
         let cancel = self.cancel.clone();
+        let restart = self.restart.clone();
+        let mut paused = self.paused.subscribe();
+        let mut dependencies = self.dependencies.clone();
+        let conditions = Arc::clone(&self.conditions);
+        let orphans = self.orphans.clone();
+        let secrets = self.secrets.clone();
         let md = md.to_owned();
+        let name = Self::instance_name(&md, self.replica);
 
         tokio::spawn(async move {
             let mut timeout = 1u64;
             let mut is_ok = true;
+            let mut started = false;
+
+            // The inputs of the module are piped into the running process, they are held
+            // while the process is paused instead of being dropped
+            let (input_tx, input_rx) = mpsc::channel(8);
+            let input_rx = Arc::new(Mutex::new(input_rx));
+            let mut wired = false;
 
             while is_ok {
+                // A paused process is not started until it is resumed
+                if *paused.borrow() {
+                    state.lock().await.set(State::Paused);
+
+                    select! {
+                        _ = wait_until(&mut paused, false) => {}
+                        _ = cancel.notified() => break,
+                    }
+                    timeout = 1;
+                }
+
+                // The modules which the process depends on have to come up first
+                if !*dependencies.borrow() {
+                    state.lock().await.set(State::Waiting);
+
+                    select! {
+                        _ = wait_until(&mut dependencies, true) => {}
+                        _ = cancel.notified() => break,
+                    }
+                    timeout = 1;
+                    continue;
+                }
+
                 log::debug!("starting process");
 
-                let bin = Self::setup_binary(&md).await;
-                if let Err(err) = &bin {
-                    let mut state = state.lock().await;
-                    state.set(State::Error(err.to_string()));
+                let instance = Self::start_name(&name);
+                let setup = Self::setup_binary(&md).await.and_then(|bin| {
+                    let security = Security::from_module(&md)?;
+                    // The config is materialized on every start so that the secrets
+                    // updated meanwhile are picked up
+                    let config = ModuleConfig::from_module(&md)?
+                        .materialize(&instance, &secrets, &security)?;
+
+                    let limits = Limits::from_module(&md);
+                    let cgroup = Self::setup_cgroup(&instance, &limits)?.map(Arc::new);
 
-                    log::error!("failed to setup process binary: {}", err);
+                    Ok((
+                        bin,
+                        security,
+                        Sandbox::from_module(&md)?,
+                        Group::from_module(&md)?,
+                        config,
+                        limits,
+                        cgroup,
+                    ))
+                });
+                if let Err(err) = &setup {
+                    state.lock().await.set(State::Error(err.to_string()));
+
+                    log::error!("failed to setup process: {}", err);
 
                     // Exponential backoff
-                    tokio::time::sleep(std::time::Duration::from_secs(timeout)).await;
-                    timeout *= 2;
+                    select! {
+                        _ = tokio::time::sleep(std::time::Duration::from_secs(timeout)) => timeout *= 2,
+                        _ = cancel.notified() => break,
+                    }
 
                     continue;
                 }
 
-                let bin = bin.ok().unwrap();
+                let (bin, security, sandbox, group, config, limits, cgroup) = setup.ok().unwrap();
 
                 log::debug!("setup process binary at: {}", bin);
 
                 let (stdout_tx, stdout_rx) = mpsc::channel(8);
                 let (stdin_tx, stdin_rx) = mpsc::channel(8);
 
-                let (data_rx, log_rx) = Self::split_stdout(stdout_rx);
+                let (data_rx, log_rx) = Self::split_stdout(stdout_rx, Arc::clone(&conditions));
 
-                if let Ok(mut process) = Process::new(bin, stdout_tx, stdin_rx) {
-                    {
-                        let mut state = state.lock().await;
-                        state.set(State::Running);
+                // Processes of the module adopted from an earlier run must not run
+                // alongside the new one
+                orphans.release(&name).await;
+
+                let oom_kills = cgroup.as_deref().map(Cgroup::oom_kills).unwrap_or_default();
+                let opts = Options {
+                    limits,
+                    cgroup: cgroup.as_deref().map(Cgroup::procs_fd),
+                    security,
+                    sandbox,
+                    tty: md.spec.as_ref().map(|spec| spec.tty).unwrap_or_default(),
+                    logs: false,
+                    env: config.env(),
+                };
+
+                // The init and sidecar processes write their output to the logs
+                let (group_tx, group_rx) = mpsc::channel(8);
+                eb.stream_logs(group_rx);
+
+                // The init processes have to complete before the process is started
+                let init = select! {
+                    res = group.run_init(&opts, &group_tx) => res,
+                    _ = cancel.notified() => break,
+                };
+                let sidecars = init.and_then(|()| group.start_sidecars(&opts, &group_tx));
+                let sidecars = match sidecars {
+                    Ok(sidecars) => sidecars,
+                    Err(err) => {
+                        log::error!("failed to setup process group: {}", err);
+                        state.lock().await.set(State::Error(err.to_string()));
+
+                        // Exponential backoff, cut short by a restart, a pause or the
+                        // dependencies going down
+                        select! {
+                            _ = tokio::time::sleep(std::time::Duration::from_secs(timeout)) => timeout *= 2,
+                            _ = restart.notified() => timeout = 1,
+                            _ = wait_until(&mut paused, true) => {}
+                            _ = wait_until(&mut dependencies, false) => {}
+                            _ = cancel.notified() => break,
+                        }
+                        continue;
                     }
+                };
 
-                    log::debug!("Process started");
+                match Process::new(bin, stdout_tx, stdin_rx, opts.clone()) {
+                    Ok(mut process) => {
+                        if started {
+                            metrics::MODULE_RESTARTS.with_label_values(&[&name]).inc();
+                        }
+                        started = true;
+                        conditions.send_modify(|conditions| {
+                            conditions.running = true;
+                            conditions.ready = false;
+                        });
 
-                    // Wire the process channels with the event bus
-                    eb.stream_data(data_rx);
-                    eb.stream_logs(log_rx);
-                    eb.recv_data(stdin_tx);
+                        let pid = process.id().unwrap_or_default();
+                        orphans.record(&name, pid);
 
-                    select! {
-                        status = process.wait_on_child() => {
-                            match status {
-                                Ok(status) => {
-                                    let mut state = state.lock().await;
-                                    state.set(State::Exit(status));
-                                }
-                                Err(err) => {
-                                    let mut state = state.lock().await;
-                                    state.set(State::Error(err.to_string()));
-                                }
-                            }
+                        {
+                            let mut state = state.lock().await;
+                            state.set(State::Running);
                         }
-                        _ = cancel.notified() => {
-                            is_ok = false;
+                        running.lock().await.insert(
+                            0,
+                            Running {
+                                pidfd: process.pidfd(),
+                                terminal: process.terminal(),
+                                context: exec::Context::new(pid, opts, cgroup.clone()),
+                            },
+                        );
+
+                        log::debug!("Process started");
+
+                        // Wire the process channels with the event bus
+                        eb.stream_data(data_rx);
+                        eb.stream_logs(log_rx);
+                        if !wired {
+                            eb.recv_data(input_tx.clone());
+                            wired = true;
+                        }
+                        let input = tokio::spawn(pipe_input(Arc::clone(&input_rx), stdin_tx));
+
+                        let sampler = Self::sample_usage(
+                            Some(pid),
+                            cgroup.clone(),
+                            Arc::clone(&usage),
+                            0,
+                            name.clone(),
+                        );
+
+                        let mut interrupted = false;
+                        let exit = select! {
+                            status = process.wait_on_child() => status.map_err(anyhow::Error::from),
+                            _ = cancel.notified() => {
+                                is_ok = false;
+
+                                log::debug!("received process termination");
+
+                                process.terminate().await
+                            }
+                            _ = restart.notified() => {
+                                interrupted = true;
+
+                                log::debug!("received process restart");
+
+                                process.terminate().await
+                            }
+                            _ = wait_until(&mut paused, true) => {
+                                interrupted = true;
+
+                                log::debug!("received process pause");
+
+                                process.terminate().await
+                            }
+                            _ = wait_until(&mut dependencies, false) => {
+                                interrupted = true;
+
+                                log::debug!("dependencies of the process are no longer met");
+
+                                process.terminate().await
+                            }
+                        };
+
+                        // The sidecars do not outlive the process
+                        Group::stop_sidecars(sidecars).await;
+
+                        // Let go of the dead process so that no more commands are run in its
+                        // context and the attached clients are detached
+                        running.lock().await.remove(&0);
 
-                            log::debug!("received process termination");
+                        // The process is not reported as exited until all of its descendants are gone
+                        let exit = match process.reap_tree(cgroup.as_deref()).await {
+                            Ok(()) => exit,
+                            Err(err) => Err(err),
+                        };
+                        orphans.forget(pid);
 
-                            match process.terminate().await {
-                                Ok(status) => {
-                                    let mut state = state.lock().await;
+                        let succeeded = matches!(&exit, Ok(status) if status.success());
+                        conditions.send_modify(|conditions| {
+                            conditions.running = false;
+                            conditions.ready = false;
+                            conditions.completed |= succeeded;
+                        });
+
+                        match exit {
+                            Ok(status) => {
+                                metrics::MODULE_LAST_EXIT_CODE
+                                    .with_label_values(&[&name])
+                                    .set(metrics::exit_code(&status));
+
+                                let oom_killed = matches!(
+                                    &cgroup,
+                                    Some(cgroup) if cgroup.oom_kills() > oom_kills
+                                );
+
+                                let mut state = state.lock().await;
+                                if oom_killed {
+                                    state.set(State::OOMKilled(status));
+                                } else {
                                     state.set(State::Exit(status));
                                 }
-                                Err(err) => {
-                                    let mut state = state.lock().await;
-                                    state.set(State::Error(err.to_string()));
-                                }
                             }
+                            Err(err) => {
+                                let mut state = state.lock().await;
+                                state.set(State::Error(err.to_string()));
+                            }
+                        }
+
+                        // Stop reporting the usage of the dead process
+                        sampler.abort();
+                        Self::clear_usage(&usage, 0, &name).await;
+
+                        // Cleanup the module event bus, the input subscriptions of a paused
+                        // process are kept so that its inputs wait for it to be resumed
+                        input.abort();
+                        if !*paused.borrow() {
+                            eb.cleanup().await;
+                            wired = false;
                         }
+
+                        // A process stopped on request is restarted or paused right away
+                        if interrupted {
+                            timeout = 1;
+                            continue;
+                        }
+                    }
+                    // The process could not be executed, e.g. as its cgroup, limits,
+                    // sandbox or user could not be set up
+                    Err(err) => {
+                        Group::stop_sidecars(sidecars).await;
+
+                        log::error!("failed to startup process: {}", err);
+                        state.lock().await.set(State::Error(err.to_string()));
                     }
+                }
+
+                // Exponential backoff, cut short by a restart, a pause or the dependencies
+                // going down, a deleted module is not started again
+                select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(timeout)) => timeout *= 2,
+                    _ = restart.notified() => timeout = 1,
+                    _ = wait_until(&mut paused, true) => {}
+                    _ = wait_until(&mut dependencies, false) => {}
+                    _ = cancel.notified() => break,
+                }
+            }
+
+            eb.cleanup().await;
+            Self::clear_metrics(&name);
+        });
+    }
+
+    /// run_scheduled launches the process defined in the module definition at every
+    /// tick of the schedule, the ticks which come while an earlier run is still going
+    /// on are handled as per the concurrency policy of the schedule
+    fn run_scheduled(&mut self, md: &base::Module, mut eb: ModuleEventBus, schedule: Schedule) {
+        let state = Arc::clone(&self.process_state);
+        let usage = Arc::clone(&self.usage);
+        let running = Arc::clone(&self.running);
+        let history = Arc::clone(&self.history);
+        let cancel = self.cancel.clone();
+        let restart = self.restart.clone();
+        let mut paused = self.paused.subscribe();
+        let mut dependencies = self.dependencies.clone();
+        let conditions = Arc::clone(&self.conditions);
+        let orphans = self.orphans.clone();
+        let secrets = self.secrets.clone();
+        let md = md.to_owned();
+        let name = Self::instance_name(&md, self.replica);
 
-                    // Cleanup the module event bus
-                    eb.cleanup().await;
+        tokio::spawn(async move {
+            orphans.release(&name).await;
+
+            // The inputs of the module are piped into the latest run
+            let (input_tx, mut input_rx) = mpsc::channel(8);
+            eb.recv_data(input_tx);
+            let mut stdin: Option<mpsc::Sender<Mail>> = None;
+
+            let mut jobs = JoinSet::new();
+            let mut stops: HashMap<u64, Arc<Notify>> = HashMap::new();
+            let stop_all = |stops: &mut HashMap<u64, Arc<Notify>>| {
+                stops.drain().for_each(|(_, stop)| stop.notify_one());
+            };
+
+            loop {
+                let is_paused = *paused.borrow();
+                let is_met = *dependencies.borrow();
+                let next = schedule.next();
+
+                state.lock().await.set(if is_paused {
+                    State::Paused
+                } else if !jobs.is_empty() {
+                    State::Running
+                } else if !is_met {
+                    State::Waiting
                 } else {
-                    log::error!("failed to startup process - init crashed");
-                    let mut state = state.lock().await;
-                    state.set(State::InitCrashLoopBackOff);
+                    State::Scheduled
+                });
+                conditions.send_if_modified(|conditions| {
+                    let running = !jobs.is_empty();
+                    let modified = conditions.running != running;
+                    conditions.running = running;
+                    conditions.ready &= running;
+                    modified
+                });
+
+                select! {
+                    _ = async {
+                        match next {
+                            Some(next) => tokio::time::sleep(next).await,
+                            None => std::future::pending().await,
+                        }
+                    } => {}
+                    // A restart launches the process right away
+                    _ = restart.notified() => {}
+                    Some(res) = jobs.join_next() => {
+                        match res {
+                            Ok((id, outcome)) => {
+                                stops.remove(&id);
+                                Self::finish_run(&history, &conditions, id, outcome).await;
+                            }
+                            Err(err) => log::error!("scheduled run crashed: {}", err),
+                        }
+                        continue;
+                    }
+                    Some(mail) = input_rx.recv() => {
+                        if let Some(Err(err)) = stdin.as_ref().map(|stdin| stdin.try_send(mail)) {
+                            log::warn!("failed to pipe input into the latest run: {}", err);
+                        }
+                        continue;
+                    }
+                    _ = wait_until(&mut paused, !is_paused) => {
+                        if !is_paused {
+                            log::debug!("received process pause");
+                            stop_all(&mut stops);
+                        }
+                        continue;
+                    }
+                    _ = wait_until(&mut dependencies, !is_met) => {
+                        if is_met {
+                            log::debug!("dependencies of the process are no longer met");
+                            stop_all(&mut stops);
+                        }
+                        continue;
+                    }
+                    _ = cancel.notified() => break,
+                }
+
+                if is_paused {
+                    continue;
+                }
+
+                if !is_met {
+                    log::debug!("skipping run as the dependencies are not met");
+                    history
+                        .lock()
+                        .await
+                        .start(Outcome::Skipped, schedule.history_limit);
+                    continue;
                 }
 
-                // Exponential backoff
-                tokio::time::sleep(std::time::Duration::from_secs(timeout)).await;
-                timeout *= 2;
+                if !jobs.is_empty() {
+                    match schedule.policy {
+                        Policy::Forbid => {
+                            log::debug!("skipping run as an earlier run is still going on");
+                            history
+                                .lock()
+                                .await
+                                .start(Outcome::Skipped, schedule.history_limit);
+                            continue;
+                        }
+                        Policy::Replace => {
+                            stop_all(&mut stops);
+                            while let Some(res) = jobs.join_next().await {
+                                if let Ok((id, outcome)) = res {
+                                    Self::finish_run(&history, &conditions, id, outcome).await;
+                                }
+                            }
+                        }
+                        Policy::Allow => {}
+                    }
+                }
+
+                log::debug!("starting scheduled run");
+
+                let (stdout_tx, stdout_rx) = mpsc::channel(8);
+                let (stdin_tx, stdin_rx) = mpsc::channel(8);
+
+                let (data_rx, log_rx) = Self::split_stdout(stdout_rx, Arc::clone(&conditions));
+                eb.stream_data(data_rx);
+                eb.stream_logs(log_rx);
+                stdin = Some(stdin_tx);
+
+                // The init and sidecar processes write their output to the logs
+                let (logs_tx, logs_rx) = mpsc::channel(8);
+                eb.stream_logs(logs_rx);
+
+                let id = history
+                    .lock()
+                    .await
+                    .start(Outcome::Running, schedule.history_limit);
+                let stop = Arc::new(Notify::new());
+                stops.insert(id, Arc::clone(&stop));
+
+                jobs.spawn(Self::run_job(Job {
+                    id,
+                    md: md.clone(),
+                    name: name.clone(),
+                    stdout: stdout_tx,
+                    stdin: stdin_rx,
+                    logs: logs_tx,
+                    deadline: schedule.deadline,
+                    stop,
+                    usage: Arc::clone(&usage),
+                    running: Arc::clone(&running),
+                    orphans: orphans.clone(),
+                    secrets: secrets.clone(),
+                }));
+            }
+
+            log::debug!("received process termination");
+
+            stop_all(&mut stops);
+            while let Some(res) = jobs.join_next().await {
+                if let Ok((id, outcome)) = res {
+                    Self::finish_run(&history, &conditions, id, outcome).await;
+                }
             }
+
+            eb.cleanup().await;
+            Self::clear_metrics(&name);
         });
     }
 
+    /// finish_run records the outcome of a scheduled run, a successful run marks the
+    /// process as completed
+    async fn finish_run(
+        history: &Mutex<History>,
+        conditions: &watch::Sender<Conditions>,
+        id: u64,
+        outcome: Outcome,
+    ) {
+        if matches!(&outcome, Outcome::Exit(status) if status.success()) {
+            conditions
+                .send_if_modified(|conditions| !std::mem::replace(&mut conditions.completed, true));
+        }
+
+        history.lock().await.finish(id, outcome);
+    }
+
+    /// run_job runs the process of a scheduled module once and returns the id of the
+    /// run along with its outcome
+    async fn run_job(job: Job) -> (u64, Outcome) {
+        // Runs might overlap hence each of them gets a cgroup and a config directory of
+        // its own
+        let instance = Self::start_name(&job.name);
+
+        let setup = Self::setup_binary(&job.md).await.and_then(|bin| {
+            let security = Security::from_module(&job.md)?;
+            let config = ModuleConfig::from_module(&job.md)?.materialize(
+                &instance,
+                &job.secrets,
+                &security,
+            )?;
+
+            let limits = Limits::from_module(&job.md);
+            let cgroup = Self::setup_cgroup(&instance, &limits)?.map(Arc::new);
+
+            Ok((
+                bin,
+                security,
+                Sandbox::from_module(&job.md)?,
+                Group::from_module(&job.md)?,
+                config,
+                limits,
+                cgroup,
+            ))
+        });
+        let (bin, security, sandbox, group, config, limits, cgroup) = match setup {
+            Ok(setup) => setup,
+            Err(err) => {
+                log::error!("failed to setup process: {}", err);
+                return (job.id, Outcome::Error(err.to_string()));
+            }
+        };
+
+        let opts = Options {
+            limits,
+            cgroup: cgroup.as_deref().map(Cgroup::procs_fd),
+            security,
+            sandbox,
+            tty: job
+                .md
+                .spec
+                .as_ref()
+                .map(|spec| spec.tty)
+                .unwrap_or_default(),
+            logs: false,
+            env: config.env(),
+        };
+
+        // The init processes have to complete before the process is started
+        let init = select! {
+            res = group.run_init(&opts, &job.logs) => res,
+            _ = job.stop.notified() => return (job.id, Outcome::Stopped),
+        };
+        let sidecars = match init.and_then(|()| group.start_sidecars(&opts, &job.logs)) {
+            Ok(sidecars) => sidecars,
+            Err(err) => {
+                log::error!("failed to setup process group: {}", err);
+                return (job.id, Outcome::Error(err.to_string()));
+            }
+        };
+
+        let mut process = match Process::new(bin, job.stdout, job.stdin, opts.clone()) {
+            Ok(process) => process,
+            Err(err) => {
+                Group::stop_sidecars(sidecars).await;
+
+                log::error!("failed to startup process: {}", err);
+                return (job.id, Outcome::Error(err.to_string()));
+            }
+        };
+
+        let pid = process.id().unwrap_or_default();
+        job.orphans.record(&instance, pid);
+
+        job.running.lock().await.insert(
+            job.id,
+            Running {
+                pidfd: process.pidfd(),
+                terminal: process.terminal(),
+                context: exec::Context::new(pid, opts, cgroup.clone()),
+            },
+        );
+
+        let sampler = Self::sample_usage(
+            Some(pid),
+            cgroup.clone(),
+            Arc::clone(&job.usage),
+            job.id,
+            job.name.clone(),
+        );
+
+        let (exit, outcome) = select! {
+            status = process.wait_on_child() => (status.map_err(anyhow::Error::from), None),
+            _ = job.stop.notified() => (process.terminate().await, Some(Outcome::Stopped)),
+            _ = async {
+                match job.deadline {
+                    Some(deadline) => tokio::time::sleep(deadline).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                log::warn!("run of module: {} exceeded its deadline", job.name);
+
+                (process.terminate().await, Some(Outcome::DeadlineExceeded))
+            }
+        };
+
+        // The sidecars do not outlive the process
+        Group::stop_sidecars(sidecars).await;
+
+        job.running.lock().await.remove(&job.id);
+
+        let exit = match process.reap_tree(cgroup.as_deref()).await {
+            Ok(()) => exit,
+            Err(err) => Err(err),
+        };
+        job.orphans.forget(pid);
+
+        sampler.abort();
+        Self::clear_usage(&job.usage, job.id, &job.name).await;
+
+        let outcome = match (exit, outcome) {
+            (_, Some(outcome)) => outcome,
+            (Ok(status), None) => {
+                metrics::MODULE_LAST_EXIT_CODE
+                    .with_label_values(&[&job.name])
+                    .set(metrics::exit_code(&status));
+
+                Outcome::Exit(status)
+            }
+            (Err(err), None) => Outcome::Error(err.to_string()),
+        };
+
+        (job.id, outcome)
+    }
+
     /// stop will submit a stop request to the process controller but does not guarantee
     /// immediate stoppage
     pub fn stop(&self) {
         self.cancel.notify_one();
     }
 
+    /// restart stops the running process which is then started again right away
+    pub fn restart(&self) -> Result<()> {
+        if *self.paused.borrow() {
+            return Err(anyhow!("process is paused"));
+        }
+
+        // The permit is kept if the loop is not waiting yet, e.g. while it is starting
+        // the process, so that the restart is not lost
+        self.restart.notify_one();
+        Ok(())
+    }
+
+    /// pause stops the running process and keeps it from being started again until
+    /// it is resumed, the module stays registered with the event bus meanwhile
+    pub fn pause(&self) -> Result<()> {
+        if !self
+            .paused
+            .send_if_modified(|paused| !std::mem::replace(paused, true))
+        {
+            return Err(anyhow!("process is already paused"));
+        }
+
+        Ok(())
+    }
+
+    /// resume starts the paused process
+    pub fn resume(&self) -> Result<()> {
+        if !self
+            .paused
+            .send_if_modified(|paused| std::mem::replace(paused, false))
+        {
+            return Err(anyhow!("process is not paused"));
+        }
+
+        Ok(())
+    }
+
     /// get_status returns status of the running process
     pub async fn get_status(&self) -> String {
         self.process_state.lock().await.to_string()
     }
 
+    /// conditions returns a receiver of the conditions of the process
+    pub fn conditions(&self) -> watch::Receiver<Conditions> {
+        self.conditions.subscribe()
+    }
+
+    /// get_runs returns the recent runs of the process, only scheduled modules keep
+    /// track of their runs
+    pub async fn get_runs(&self) -> Vec<Run> {
+        self.history.lock().await.runs()
+    }
+
+    /// get_terminal returns the terminal of the latest running process, `None` is
+    /// returned if the process is not running or does not run on a pseudo-terminal
+    pub async fn get_terminal(&self) -> Option<Terminal> {
+        self.running
+            .lock()
+            .await
+            .values()
+            .next_back()
+            .and_then(|running| running.terminal.clone())
+    }
+
+    /// exec runs the command in the context of the latest running process and returns
+    /// a receiver of its output
+    pub async fn exec(&self, command: &[String]) -> Result<mpsc::Receiver<exec::Output>> {
+        match self.running.lock().await.values().next_back() {
+            Some(running) => running.context.exec(command),
+            None => Err(anyhow!("process is not running")),
+        }
+    }
+
+    /// signal sends the signal to every running process, there is more than one if
+    /// the runs of a scheduled module overlap
+    pub async fn signal(&self, sig: Signal) -> Result<()> {
+        let running = self.running.lock().await;
+        if running.is_empty() {
+            return Err(anyhow!("process is not running"));
+        }
+
+        for running in running.values() {
+            running.pidfd.signal(sig)?;
+        }
+
+        Ok(())
+    }
+
+    /// instance_name returns the name under which the replica of the module is tracked
+    /// in cgroups, metrics and orphan records, the replicas of a module with more than
+    /// one replica are suffixed with their index, e.g. `cat#1`, the separator is not
+    /// allowed in module names so that the replica never clashes with another module
+    fn instance_name(md: &base::Module, replica: usize) -> String {
+        let key = utility::module_core_key(md).unwrap_or_default();
+
+        match md.spec.as_ref().map(|spec| spec.replicas) {
+            Some(replicas) if replicas > 1 => format!("{}#{}", key, replica),
+            _ => key,
+        }
+    }
+
+    /// start_name returns a name which is unique to a single start of the process of
+    /// the module, a process which replaces another one, e.g. as the module is applied
+    /// again, might start before the other one is gone hence they must not share their
+    /// cgroup or config directory
+    fn start_name(name: &str) -> String {
+        format!("{}.{}", name, Uuid::new_v4().to_simple())
+    }
+
+    /// setup_cgroup creates a cgroup which keeps track of the processes of the module
+    /// and enforces the given limits, `None` is returned if cgroups are unavailable in
+    /// which case the limits are enforced using rlimits
+    ///
+    /// An error is returned if cgroups are unavailable and some of the limits can only
+    /// be enforced by a cgroup, the process is not started without its limits
+    fn setup_cgroup(name: &str, limits: &Limits) -> Result<Option<Cgroup>> {
+        match Cgroup::create(name, limits) {
+            Ok(cgroup) => Ok(Some(cgroup)),
+            Err(err) if limits.is_empty() => {
+                log::debug!("failed to setup cgroup: {}", err);
+                Ok(None)
+            }
+            Err(err) => {
+                limits
+                    .check(false)
+                    .map_err(|reason| anyhow!("failed to setup cgroup: {} - {}", err, reason))?;
+
+                log::warn!("failed to setup cgroup - falling back to rlimits: {}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    /// get_usage returns the last sampled resource usage of the running processes
+    pub async fn get_usage(&self) -> Option<Usage> {
+        Self::total_usage(&*self.usage.lock().await)
+    }
+
+    /// total_usage adds up the usage of the runs, `None` is returned if there are none
+    fn total_usage(usage: &BTreeMap<u64, Usage>) -> Option<Usage> {
+        usage.values().fold(None, |total, usage| {
+            let mut total = total.unwrap_or_default();
+            total.add(usage);
+            Some(total)
+        })
+    }
+
+    /// clear_usage stops reporting the usage of the run which is done
+    async fn clear_usage(usage: &Mutex<BTreeMap<u64, Usage>>, run: u64, name: &str) {
+        let mut usage = usage.lock().await;
+        usage.remove(&run);
+        Self::report_usage(name, Self::total_usage(&usage).as_ref());
+    }
+
+    /// sample_usage periodically samples the resource usage of the process with
+    /// the given pid and its cgroup until the returned handle is aborted, the metrics
+    /// of the module report the usage of all of its runs
+    fn sample_usage(
+        pid: Option<u32>,
+        cgroup: Option<Arc<Cgroup>>,
+        usage: Arc<Mutex<BTreeMap<u64, Usage>>>,
+        run: u64,
+        name: String,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let pid = match pid {
+                Some(pid) => pid,
+                None => return,
+            };
+
+            let mut interval = tokio::time::interval(USAGE_SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let cgroup = cgroup.clone();
+                let sample = match tokio::task::spawn_blocking(move || {
+                    Usage::sample(pid, cgroup.as_deref())
+                })
+                .await
+                {
+                    Ok(Ok(sample)) => sample,
+                    Ok(Err(err)) => {
+                        log::debug!("failed to sample resource usage: {}", err);
+                        continue;
+                    }
+                    Err(err) => {
+                        log::error!("resource usage sampler crashed: {}", err);
+                        return;
+                    }
+                };
+
+                let mut usage = usage.lock().await;
+                usage.insert(run, sample);
+                Self::report_usage(&name, Self::total_usage(&usage).as_ref());
+            }
+        })
+    }
+
+    /// report_usage updates the resource usage metrics of the module, `None` clears them
+    fn report_usage(name: &str, usage: Option<&Usage>) {
+        match usage {
+            Some(usage) => {
+                metrics::MODULE_CPU_SECONDS
+                    .with_label_values(&[name])
+                    .set(usage.cpu_seconds);
+                metrics::MODULE_RSS_BYTES
+                    .with_label_values(&[name])
+                    .set(usage.rss_bytes as i64);
+                metrics::MODULE_OPEN_FDS
+                    .with_label_values(&[name])
+                    .set(usage.open_fds as i64);
+                metrics::MODULE_THREADS
+                    .with_label_values(&[name])
+                    .set(usage.threads as i64);
+                metrics::MODULE_IO_BYTES
+                    .with_label_values(&[name, "read"])
+                    .set(usage.read_bytes as i64);
+                metrics::MODULE_IO_BYTES
+                    .with_label_values(&[name, "write"])
+                    .set(usage.write_bytes as i64);
+            }
+            None => {
+                let _ = metrics::MODULE_CPU_SECONDS.remove_label_values(&[name]);
+                let _ = metrics::MODULE_RSS_BYTES.remove_label_values(&[name]);
+                let _ = metrics::MODULE_OPEN_FDS.remove_label_values(&[name]);
+                let _ = metrics::MODULE_THREADS.remove_label_values(&[name]);
+                let _ = metrics::MODULE_IO_BYTES.remove_label_values(&[name, "read"]);
+                let _ = metrics::MODULE_IO_BYTES.remove_label_values(&[name, "write"]);
+            }
+        }
+    }
+
+    /// clear_metrics drops the series of the module which are not cleared along with its
+    /// usage, once the module is deleted
+    fn clear_metrics(name: &str) {
+        let _ = metrics::MODULE_RESTARTS.remove_label_values(&[name]);
+        let _ = metrics::MODULE_LAST_EXIT_CODE.remove_label_values(&[name]);
+    }
+
+    /// split_stdout splits the stdout of the process into data and logs, the process
+    /// is marked as ready once it writes to its stdout
     fn split_stdout(
         mut stdout: mpsc::Receiver<Mail>,
+        conditions: Arc<watch::Sender<Conditions>>,
     ) -> (mpsc::Receiver<Mail>, mpsc::Receiver<Mail>) {
         let (data_tx, data_rx) = mpsc::channel(8);
         let (log_tx, log_rx) = mpsc::channel(8);
 
         tokio::spawn(async move {
+            let mut ready = false;
             while let Some(mail) = stdout.recv().await {
+                if !ready {
+                    ready = true;
+                    conditions.send_if_modified(|conditions| {
+                        !std::mem::replace(&mut conditions.ready, true)
+                    });
+                }
+
                 match mail.typ {
                     mail::data_type::LOG => {
                         if log_tx.send(mail).await.is_err() {
@@ -175,7 +1069,15 @@ impl Controller {
         }
 
         if location.starts_with("http") {
-            return Self::dowload_binary(Self::get_binary_location(md)?).await;
+            let timer = metrics::DOWNLOAD_DURATION.start_timer();
+            let res = Self::dowload_binary(Self::get_binary_location(md)?).await;
+            timer.observe_duration();
+
+            if res.is_err() {
+                metrics::DOWNLOAD_FAILURES.inc();
+            }
+
+            return res;
         }
 
         Err(anyhow!(
@@ -246,3 +1148,58 @@ impl Controller {
         Ok(path)
     }
 }
+
+/// pipe_input forwards the inputs of the module into the stdin of the process till it is
+/// aborted, the inputs stay in `rx` meanwhile for the next process to pick up
+async fn pipe_input(rx: Arc<Mutex<mpsc::Receiver<Mail>>>, stdin: mpsc::Sender<Mail>) {
+    let mut rx = rx.lock().await;
+    while let Some(mail) = rx.recv().await {
+        if stdin.send(mail).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// wait_until waits until the flag is set to `value`, it never returns if the flag
+/// can no longer change
+async fn wait_until(rx: &mut watch::Receiver<bool>, value: bool) {
+    // The reference returned is not Send hence it must not be held across awaits
+    if rx.wait_for(|flag| *flag == value).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mail(data: &[u8]) -> Mail {
+        Mail {
+            typ: mail::data_type::DATA,
+            size: data.len() as u64,
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipe_input() {
+        let (input_tx, input_rx) = mpsc::channel(8);
+        let input_rx = Arc::new(Mutex::new(input_rx));
+
+        let (stdin_tx, mut stdin_rx) = mpsc::channel(8);
+        let input = tokio::spawn(pipe_input(Arc::clone(&input_rx), stdin_tx));
+        assert!(input_tx.send(mail(b"running")).await.is_ok());
+        assert_eq!(stdin_rx.recv().await.unwrap().data, b"running");
+
+        // The inputs sent while no process is running are held for the next one
+        input.abort();
+        assert!(input.await.is_err());
+        assert!(stdin_rx.recv().await.is_none());
+        assert!(input_tx.send(mail(b"paused")).await.is_ok());
+
+        let (stdin_tx, mut stdin_rx) = mpsc::channel(8);
+        let input = tokio::spawn(pipe_input(Arc::clone(&input_rx), stdin_tx));
+        assert_eq!(stdin_rx.recv().await.unwrap().data, b"paused");
+        input.abort();
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    collections::VecDeque,
+    process::ExitStatus,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+
+use crate::proto::base;
+
+const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// Policy decides what happens when the schedule ticks while an earlier run of the
+/// module is still going on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Forbid skips the tick
+    Forbid,
+    /// Replace stops the earlier runs before starting a new one
+    Replace,
+    /// Allow starts a new run alongside the earlier ones
+    Allow,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "forbid" => Ok(Self::Forbid),
+            "replace" => Ok(Self::Replace),
+            "allow" => Ok(Self::Allow),
+            _ => Err(anyhow!(
+                "invalid concurrency policy \"{}\" - expected \"forbid\", \"replace\" or \"allow\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Schedule decides when the process of a module is launched
+pub struct Schedule {
+    cron: cron::Schedule,
+    pub policy: Policy,
+    /// deadline is the time after which a run is stopped
+    pub deadline: Option<Duration>,
+    /// history_limit is the number of recent runs which are kept
+    pub history_limit: usize,
+}
+
+impl Schedule {
+    /// from_module takes in a module definition and returns its schedule, `None` is
+    /// returned if the module is not scheduled
+    pub fn from_module(md: &base::Module) -> Result<Option<Self>> {
+        let schedule = match &md.spec {
+            Some(base::ModuleSpec {
+                schedule: Some(schedule),
+                ..
+            }) if !schedule.cron.is_empty() => schedule,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            cron: Self::parse_cron(&schedule.cron)?,
+            policy: schedule.concurrency_policy.parse()?,
+            deadline: match schedule.deadline_seconds {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            history_limit: match schedule.history_limit {
+                0 => DEFAULT_HISTORY_LIMIT,
+                limit => limit as usize,
+            },
+        }))
+    }
+
+    /// parse_cron takes in a cron expression with five fields (minute, hour, day of
+    /// month, month and day of week) or six with the seconds in front
+    fn parse_cron(expr: &str) -> Result<cron::Schedule> {
+        let expr = match expr.split_whitespace().count() {
+            5 => format!("0 {}", expr),
+            6 => expr.to_string(),
+            _ => {
+                return Err(anyhow!(
+                    "invalid cron expression \"{}\" - expected 5 or 6 fields",
+                    expr
+                ))
+            }
+        };
+
+        cron::Schedule::from_str(&expr)
+            .map_err(|err| anyhow!("invalid cron expression \"{}\" - {}", expr, err))
+    }
+
+    /// next returns the time left till the next tick of the schedule in local time,
+    /// `None` is returned if the schedule never ticks again
+    pub fn next(&self) -> Option<Duration> {
+        self.cron
+            .upcoming(Local)
+            .next()
+            .map(|tick| (tick - Local::now()).to_std().unwrap_or_default())
+    }
+}
+
+/// Outcome is how a run of a scheduled module ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Running,
+    /// Skipped runs were never started as an earlier run was still going on
+    Skipped,
+    Exit(ExitStatus),
+    DeadlineExceeded,
+    /// Stopped runs were stopped by a replacing run, a pause or a deletion
+    Stopped,
+    Error(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::Running => write!(f, "Running"),
+            Self::Skipped => write!(f, "Skipped"),
+            Self::Exit(status) => write!(f, "Exit: {}", status),
+            Self::DeadlineExceeded => write!(f, "DeadlineExceeded"),
+            Self::Stopped => write!(f, "Stopped"),
+            Self::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Run is a single run of a scheduled module
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub id: u64,
+    pub start_time: SystemTime,
+    pub end_time: Option<SystemTime>,
+    pub outcome: Outcome,
+}
+
+impl Run {
+    pub fn to_proto(&self) -> base::module_status::Run {
+        let timestamp = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default()
+        };
+
+        base::module_status::Run {
+            start_time: timestamp(self.start_time),
+            end_time: self.end_time.map(timestamp).unwrap_or_default(),
+            result: self.outcome.to_string(),
+        }
+    }
+}
+
+/// History keeps track of the recent runs of a scheduled module
+#[derive(Default)]
+pub struct History {
+    runs: VecDeque<Run>,
+    next_id: u64,
+}
+
+impl History {
+    /// start records a new run with the given outcome and returns its id
+    pub fn start(&mut self, outcome: Outcome, limit: usize) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let now = SystemTime::now();
+        self.runs.push_back(Run {
+            id,
+            start_time: now,
+            end_time: (outcome != Outcome::Running).then_some(now),
+            outcome,
+        });
+
+        while self.runs.len() > limit {
+            self.runs.pop_front();
+        }
+
+        id
+    }
+
+    /// finish records the outcome of the run with the given id
+    pub fn finish(&mut self, id: u64, outcome: Outcome) {
+        if let Some(run) = self.runs.iter_mut().find(|run| run.id == id) {
+            run.end_time = Some(SystemTime::now());
+            run.outcome = outcome;
+        }
+    }
+
+    /// runs returns the recent runs, oldest first
+    pub fn runs(&self) -> Vec<Run> {
+        self.runs.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_module() {
+        assert!(Schedule::from_module(&base::Module::default())
+            .unwrap()
+            .is_none());
+
+        let md = |cron: &str, policy: &str| base::Module {
+            spec: Some(base::ModuleSpec {
+                schedule: Some(base::module_spec::Schedule {
+                    cron: cron.to_string(),
+                    concurrency_policy: policy.to_string(),
+                    deadline_seconds: 30,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let schedule = Schedule::from_module(&md("*/5 * * * *", "replace"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(schedule.policy, Policy::Replace);
+        assert_eq!(schedule.deadline, Some(Duration::from_secs(30)));
+        assert_eq!(schedule.history_limit, DEFAULT_HISTORY_LIMIT);
+        assert!(schedule.next().unwrap() <= Duration::from_secs(5 * 60));
+
+        assert!(Schedule::from_module(&md("* * * * * *", "")).is_ok());
+        assert!(Schedule::from_module(&md("* * *", "")).is_err());
+        assert!(Schedule::from_module(&md("61 * * * *", "")).is_err());
+        assert!(Schedule::from_module(&md("* * * * *", "sometimes")).is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+
+        let first = history.start(Outcome::Running, 2);
+        history.start(Outcome::Skipped, 2);
+        history.finish(first, Outcome::DeadlineExceeded);
+        assert_eq!(history.runs()[0].outcome, Outcome::DeadlineExceeded);
+        assert!(history.runs()[0].end_time.is_some());
+
+        history.start(Outcome::Running, 2);
+        let runs = history.runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].outcome, Outcome::Skipped);
+        assert_eq!(runs[1].outcome, Outcome::Running);
+        assert!(runs[1].end_time.is_none());
+    }
+}
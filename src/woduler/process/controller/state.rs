@@ -1,20 +1,38 @@
 use std::process::ExitStatus;
 
-#[derive(Clone)]
+use crate::metrics;
+
 pub struct ProcessState {
     state: State,
 }
 
 impl ProcessState {
     pub fn new() -> Self {
+        metrics::MODULES
+            .with_label_values(&[State::Init.phase()])
+            .inc();
+
         Self { state: State::Init }
     }
 
     pub fn set(&mut self, state: State) {
+        metrics::MODULES
+            .with_label_values(&[self.state.phase()])
+            .dec();
+        metrics::MODULES.with_label_values(&[state.phase()]).inc();
+
         self.state = state;
     }
 }
 
+impl Drop for ProcessState {
+    fn drop(&mut self) {
+        metrics::MODULES
+            .with_label_values(&[self.state.phase()])
+            .dec();
+    }
+}
+
 impl std::string::ToString for ProcessState {
     fn to_string(&self) -> String {
         self.state.to_string()
@@ -24,19 +42,41 @@ impl std::string::ToString for ProcessState {
 #[derive(Clone)]
 pub enum State {
     Init,
-    InitCrashLoopBackOff,
     Running,
+    Paused,
+    Scheduled,
+    Waiting,
     Error(String),
     Exit(ExitStatus),
+    OOMKilled(ExitStatus),
+}
+
+impl State {
+    /// phase returns the name of the state without any of the details
+    pub fn phase(&self) -> &'static str {
+        match &self {
+            Self::Init => "Init",
+            Self::Running => "Running",
+            Self::Paused => "Paused",
+            Self::Scheduled => "Scheduled",
+            Self::Waiting => "Waiting",
+            Self::Exit(_) => "Exit",
+            Self::OOMKilled(_) => "OOMKilled",
+            Self::Error(_) => "Error",
+        }
+    }
 }
 
 impl std::string::ToString for State {
     fn to_string(&self) -> String {
         match &self {
             Self::Init => "Init".to_string(),
-            Self::InitCrashLoopBackOff => "InitCrashLoopBackoff".to_string(),
             Self::Running => "Running".to_string(),
+            Self::Paused => "Paused".to_string(),
+            Self::Scheduled => "Scheduled".to_string(),
+            Self::Waiting => "Waiting".to_string(),
             Self::Exit(status) => format!("Exit: {}", status),
+            Self::OOMKilled(status) => format!("OOMKilled: {}", status),
             Self::Error(err) => err.clone(),
         }
     }
@@ -1,17 +1,63 @@
+use anyhow::anyhow;
 use nix::sys::signal;
-use nix::unistd::Pid;
+use nix::unistd::{self, Pid};
 use tokio::{
-    io::{AsyncRead, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
     process,
     sync::mpsc,
 };
 
-use super::mail::Mail;
+use super::limits::{Cgroup, Limits};
+use super::mail::{self, Mail};
+use super::orphans;
+use super::pidfd::PidFd;
+use super::pty::{Pty, Terminal};
+use super::sandbox::{Sandbox, FORWARDED_SIGNALS};
+use super::security::Security;
+use super::usage::Stat;
 use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::{io::RawFd, process::ExitStatusExt};
 use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time given to the process to exit after `SIGINT` before it is killed
+const TERMINATE_GRACE: Duration = Duration::from_secs(10);
+/// Time given to the leftover processes to exit after `SIGTERM` and after `SIGKILL`
+const REAP_GRACE: Duration = Duration::from_secs(5);
+const REAP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options configure the environment in which the process is executed
+#[derive(Clone, Default)]
+pub struct Options {
+    pub limits: Limits,
+    /// cgroup is the `cgroup.procs` file descriptor of the group which the process
+    /// is moved into before it starts executing
+    pub cgroup: Option<RawFd>,
+    pub security: Security,
+    pub sandbox: Sandbox,
+    /// tty runs the process on a pseudo-terminal instead of the `Mail` pipes, the
+    /// output of the terminal is sent as logs
+    pub tty: bool,
+    /// logs sends every line written by the process to its stdout or stderr as logs
+    /// instead of reading `Mail` from its stdout, the process gets no input
+    pub logs: bool,
+    /// env is the environment variables set for the process on top of the ones
+    /// inherited from hyperion
+    pub env: Vec<(String, OsString)>,
+}
 
 pub struct Process {
     child: process::Child,
+    /// pidfd refers to the child process, unlike the pid it never refers to another
+    /// process once the child is reaped
+    pidfd: Arc<PidFd>,
+    terminal: Option<Terminal>,
+    /// pgid is the id of the process group led by the child process
+    pgid: Pid,
 }
 
 impl Process {
@@ -22,30 +68,160 @@ impl Process {
     pub fn new(
         bin: String,
         stdout: mpsc::Sender<Mail>,
+        stdin: mpsc::Receiver<Mail>,
+        opts: Options,
+    ) -> anyhow::Result<Self> {
+        Self::spawn(&[bin], stdout, stdin, opts)
+    }
+
+    /// spawn is `new` for a command made up of the program followed by its arguments
+    pub fn spawn(
+        command: &[String],
+        stdout: mpsc::Sender<Mail>,
         mut stdin: mpsc::Receiver<Mail>,
+        opts: Options,
     ) -> anyhow::Result<Self> {
-        let mut process = process::Command::new(bin)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::null())
-            .kill_on_drop(true)
-            .spawn()?;
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("command is required"))?;
+
+        let mut cmd = process::Command::new(program);
+        cmd.args(args).envs(opts.env.clone()).kill_on_drop(true);
+
+        let pty = if opts.tty {
+            let (pty, slave) = Pty::open()?;
+            cmd.stdin(slave.try_clone()?)
+                .stdout(slave.try_clone()?)
+                .stderr(slave);
+
+            Some(pty)
+        } else if opts.logs {
+            cmd.stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped());
+
+            None
+        } else {
+            cmd.stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::null());
+
+            None
+        };
+
+        // Safety: the hook runs in between fork and exec and only makes system calls
+        unsafe {
+            cmd.pre_exec(move || {
+                // A process group of its own lets the whole tree of the process be
+                // signalled at once
+                if opts.tty {
+                    // The session also makes the terminal the controlling terminal of
+                    // the process which job control and line editing rely on
+                    unistd::setsid().map_err(|err| io::Error::from_raw_os_error(err as i32))?;
+                    if libc::ioctl(0, libc::TIOCSCTTY, 0) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                } else {
+                    unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0))
+                        .map_err(|err| io::Error::from_raw_os_error(err as i32))?;
+                }
+
+                // Joining the cgroup before exec ensures that none of the processes
+                // forked by the child escape the group
+                if let Some(fd) = opts.cgroup {
+                    unistd::write(fd, b"0")
+                        .map_err(|err| io::Error::from_raw_os_error(err as i32))?;
+                }
+
+                opts.limits.apply_rlimits(opts.cgroup.is_some())?;
+                opts.sandbox.isolate()?;
+
+                // Privileges are dropped last as the steps above might need them
+                opts.security.apply()?;
+                opts.sandbox.confine()
+            });
+        }
+
+        let mut process = orphans::spawn_child(&mut cmd)?;
+        // Release the slave side held by the command so that the terminal hangs up
+        // once the process tree exits
+        drop(cmd);
+        let pgid = match process.id() {
+            Some(pid) => Pid::from_raw(pid.try_into()?),
+            None => return Err(anyhow!("failed to get process id of the child process")),
+        };
+
+        // The child is tracked from before the spawn so the subreaper cannot reap it,
+        // its pid stays valid until it is waited on
+        let pidfd = Arc::new(PidFd::open(pgid.as_raw() as u32)?);
 
         log::debug!("Spinning up new process");
 
-        let cstdout = process.stdout.take().unwrap();
-        tokio::spawn(async move {
-            Process::observe(cstdout, stdout).await;
-        });
+        let terminal = match pty {
+            Some(pty) => {
+                // Data sent to the process has nowhere to go as its stdin is the terminal
+                tokio::spawn(async move {
+                    while stdin.recv().await.is_some() {
+                        log::debug!("dropping data sent to a process running on a terminal");
+                    }
+                });
+
+                Some(Terminal::new(pty, stdout))
+            }
+            None if opts.logs => {
+                tokio::spawn(async move {
+                    while stdin.recv().await.is_some() {
+                        log::debug!("dropping data sent to a process which takes no input");
+                    }
+                });
+
+                let cstdout = process.stdout.take().unwrap();
+                tokio::spawn(Process::forward_logs(cstdout, stdout.clone()));
+                let cstderr = process.stderr.take().unwrap();
+                tokio::spawn(Process::forward_logs(cstderr, stdout));
 
-        let mut cstdin = process.stdin.take().unwrap();
-        tokio::spawn(async move {
-            while let Some(mail) = stdin.recv().await {
-                let _ = cstdin.write(&mail.as_bytes_vec()).await;
+                None
             }
-        });
+            None => {
+                let cstdout = process.stdout.take().unwrap();
+                tokio::spawn(async move {
+                    Process::observe(cstdout, stdout).await;
+                });
 
-        Ok(Self { child: process })
+                let mut cstdin = process.stdin.take().unwrap();
+                tokio::spawn(async move {
+                    while let Some(mail) = stdin.recv().await {
+                        let _ = cstdin.write(&mail.as_bytes_vec()).await;
+                    }
+                });
+
+                None
+            }
+        };
+
+        Ok(Self {
+            child: process,
+            pidfd,
+            terminal,
+            pgid,
+        })
+    }
+
+    /// id returns the pid of the child process, `None` is returned once the
+    /// child process has been reaped
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// pidfd returns a handle to the child process which can be used to signal it
+    pub fn pidfd(&self) -> Arc<PidFd> {
+        Arc::clone(&self.pidfd)
+    }
+
+    /// terminal returns the terminal of the process, `None` is returned if the process
+    /// does not run on a pseudo-terminal
+    pub fn terminal(&self) -> Option<Terminal> {
+        self.terminal.clone()
     }
 
     /// wait_on_child will lock the child process instance and will wait for the
@@ -53,12 +229,42 @@ impl Process {
     ///
     /// # Caveats
     /// - `wait_on_child` will drop the `stdin` of the child process
+    /// - the child process is left unreaped until `reap_tree` so that the id of its
+    ///   process group cannot be reused in the meantime
     pub async fn wait_on_child(&mut self) -> std::io::Result<ExitStatus> {
-        self.child.wait().await
+        drop(self.child.stdin.take());
+
+        self.pidfd.exited().await;
+        self.exit_status()
+    }
+
+    /// exit_status reads the exit status of the exited child process without
+    /// reaping it
+    fn exit_status(&self) -> io::Result<ExitStatus> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                self.pgid.as_raw() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let status = unsafe { info.si_status() };
+        Ok(ExitStatus::from_raw(match info.si_code {
+            libc::CLD_EXITED => (status & 0xff) << 8,
+            libc::CLD_DUMPED => status | 0x80,
+            _ => status,
+        }))
     }
 
-    /// terminate sends `SIGINT` to the child process and waits for the process
-    /// to die. `terminate` should be preferred over `kill` as it allows the child
+    /// terminate sends `SIGINT` to the process group of the child process and waits
+    /// for the process to die, the group is killed if the process does not exit in
+    /// time. `terminate` should be preferred over `kill` as it allows the child
     /// process to perform cleanups
     ///
     /// # Caveats
@@ -66,25 +272,128 @@ impl Process {
     /// - terminate will drop the `stdin` of the child process **if** it hasn't been
     /// taken earlier
     pub async fn terminate(&mut self) -> anyhow::Result<ExitStatus> {
-        match self.child.id() {
-            Some(pid) => {
-                let res = signal::kill(Pid::from_raw(pid.try_into().unwrap()), signal::SIGINT);
-
-                match res {
-                    Ok(()) => {
-                        // Wait for the process to die
-                        self.child
-                            .wait()
-                            .await
-                            .map_err(|err| anyhow::anyhow!("{}", err))
-                    }
-                    Err(err) => Err(anyhow::anyhow!("failed to terminate process: {}", err)),
+        self.signal_group(signal::SIGINT)
+            .map_err(|err| anyhow!("failed to terminate process: {}", err))?;
+
+        // Wait for the process to die
+        match tokio::time::timeout(TERMINATE_GRACE, self.wait_on_child()).await {
+            Ok(status) => status.map_err(|err| anyhow!("{}", err)),
+            Err(_) => {
+                log::warn!(
+                    "process did not exit within {:?} - killing it",
+                    TERMINATE_GRACE
+                );
+
+                let _ = self.signal_group(signal::SIGKILL);
+                self.wait_on_child().await.map_err(|err| anyhow!("{}", err))
+            }
+        }
+    }
+
+    /// signal_group sends the signal to the process group of the child process as long
+    /// as the child process has not been reaped, which keeps the group id from being
+    /// reused
+    fn signal_group(&self, sig: signal::Signal) -> anyhow::Result<()> {
+        self.pidfd.signal(None)?;
+        signal::killpg(self.pgid, sig)?;
+
+        Ok(())
+    }
+
+    /// reap_tree makes sure that none of the processes spawned by the child process
+    /// outlive it, processes left in its process group or in the given cgroup are
+    /// sent `SIGTERM` and then killed if they do not exit in time, the exited child
+    /// process is reaped last
+    ///
+    /// An error is returned if any of the processes survive
+    pub async fn reap_tree(&mut self, cgroup: Option<&Cgroup>) -> anyhow::Result<()> {
+        let res = self.signal_tree(cgroup).await;
+
+        // The group is gone hence its id may be reused from here on, a child process
+        // which is still running is left alone
+        let _ = self.child.try_wait();
+
+        res
+    }
+
+    async fn signal_tree(&self, cgroup: Option<&Cgroup>) -> anyhow::Result<()> {
+        let pgid = self.pgid;
+        // The cgroup is preferred as, unlike the process group, it cannot be escaped
+        // and its processes are signalled via pidfds. The unreaped child keeps the id
+        // of the group from being reused while the group is signalled
+        let alive = || match cgroup {
+            Some(cgroup) => cgroup.is_populated(),
+            None => Self::group_alive(pgid),
+        };
+
+        if !alive() {
+            return Ok(());
+        }
+
+        log::debug!("terminating leftover processes of group: {}", pgid);
+
+        match cgroup {
+            Some(cgroup) => {
+                if let Err(err) = cgroup.signal(signal::SIGTERM) {
+                    log::warn!("failed to signal cgroup: {}", err);
                 }
             }
-            _ => Err(anyhow::anyhow!(
-                "failed to get process id of the child process"
-            )),
+            None => {
+                let _ = signal::killpg(pgid, signal::SIGTERM);
+            }
         }
+        if Self::wait_until_dead(&alive).await {
+            return Ok(());
+        }
+
+        log::warn!(
+            "leftover processes of group: {} did not exit - killing them",
+            pgid
+        );
+
+        match cgroup {
+            Some(cgroup) => {
+                if let Err(err) = cgroup.kill() {
+                    log::warn!("failed to kill cgroup: {}", err);
+                }
+            }
+            None => {
+                let _ = signal::killpg(pgid, signal::SIGKILL);
+            }
+        }
+        if Self::wait_until_dead(&alive).await {
+            return Ok(());
+        }
+
+        Err(anyhow!("processes of group {} survived termination", pgid))
+    }
+
+    /// group_alive returns true if any process of the group has yet to exit
+    fn group_alive(pgid: Pid) -> bool {
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return signal::killpg(pgid, None).is_ok(),
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+            .filter_map(|pid| Stat::read(pid).ok())
+            .any(|stat| stat.pgrp == pgid.as_raw() as u32 && stat.state != 'Z')
+    }
+
+    async fn wait_until_dead(alive: &impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+
+        while start.elapsed() < REAP_GRACE {
+            tokio::time::sleep(REAP_POLL_INTERVAL).await;
+
+            if !alive() {
+                return true;
+            }
+        }
+
+        false
     }
 
     /// observe takes in a `pipe` which is an object must implement `AsyncRead` and `Unpin` trait
@@ -114,4 +423,99 @@ impl Process {
             }
         }
     }
+
+    /// forward_logs sends every line read from the `pipe` to the `mailbox` as logs
+    async fn forward_logs<T: AsyncRead + Unpin>(pipe: T, mailbox: mpsc::Sender<Mail>) {
+        let mut lines = BufReader::new(pipe).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            let data = line.into_bytes();
+            let mail = Mail {
+                typ: mail::data_type::LOG,
+                size: data.len() as u64,
+                data,
+            };
+
+            if mailbox.send(mail).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// parse_signal takes in the name of a signal, with or without the `SIG` prefix, and
+/// returns the signal if it may be sent to a module
+///
+/// Only the signals which are relayed to the module inside a PID namespace are allowed
+pub fn parse_signal(name: &str) -> anyhow::Result<signal::Signal> {
+    let name = name.to_uppercase();
+    let sig: signal::Signal = if name.starts_with("SIG") {
+        name.parse()
+    } else {
+        format!("SIG{}", name).parse()
+    }
+    .map_err(|_| anyhow!("unknown signal \"{}\"", name))?;
+
+    if !FORWARDED_SIGNALS.contains(&sig) {
+        let allowed: Vec<&str> = FORWARDED_SIGNALS.iter().map(|sig| sig.as_str()).collect();
+        return Err(anyhow!(
+            "signal {} is not allowed - allowed signals: {}",
+            sig,
+            allowed.join(", ")
+        ));
+    }
+
+    Ok(sig)
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // A child which was not waited on is given up on, its exit status is of no
+        // use hence the subreaper may reap it
+        let _ = self.child.start_kill();
+        orphans::untrack_child(self.pgid.as_raw() as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reap_tree() {
+        let (stdout_tx, _stdout_rx) = mpsc::channel(8);
+        let (_stdin_tx, stdin_rx) = mpsc::channel(8);
+
+        // The shell exits right away leaving its child behind
+        let dir = std::env::temp_dir().join(format!("hyperion-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bin = dir.join("orphan.sh");
+        std::fs::write(&bin, "#!/bin/sh\nsleep 100 &\n").unwrap();
+        std::fs::set_permissions(&bin, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let mut process = Process::new(
+            bin.display().to_string(),
+            stdout_tx,
+            stdin_rx,
+            Options::default(),
+        )
+        .unwrap();
+        assert!(process.wait_on_child().await.unwrap().success());
+        assert!(signal::killpg(process.pgid, None).is_ok());
+
+        process.reap_tree(None).await.unwrap();
+        assert!(!Process::group_alive(process.pgid));
+        assert!(process.id().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGHUP").unwrap(), signal::SIGHUP);
+        assert_eq!(parse_signal("usr1").unwrap(), signal::SIGUSR1);
+        assert!(parse_signal("SIGKILL").is_err());
+        assert!(parse_signal("SIGNOPE").is_err());
+    }
 }
@@ -0,0 +1,214 @@
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io,
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use nix::{
+    sched::{setns, CloneFlags},
+    unistd::{self, Pid},
+};
+use tokio::{io::AsyncReadExt, process, select, sync::mpsc};
+
+use super::{core::Options, limits::Cgroup, orphans, sandbox::Sandbox};
+
+/// Output is a piece of the output of a command run in the context of a module
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// Exit is the last output of the command
+    Exit(ExitStatus),
+}
+
+/// Context is the environment in which the process of a module runs, commands run
+/// in the context share the user, environment, cgroup, namespaces and working
+/// directory of the process
+#[derive(Clone)]
+pub struct Context {
+    pid: u32,
+    opts: Options,
+    cgroup: Option<Arc<Cgroup>>,
+}
+
+impl Context {
+    /// new takes in the pid of the process of a module along with the options and
+    /// the cgroup it was spawned with and returns its context
+    pub fn new(pid: u32, opts: Options, cgroup: Option<Arc<Cgroup>>) -> Self {
+        Self { pid, opts, cgroup }
+    }
+
+    /// exec runs the command in the context and returns a receiver of its output, the
+    /// command is killed if the receiver is dropped before the command exits
+    pub fn exec(&self, command: &[String]) -> Result<mpsc::Receiver<Output>> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("command is required"))?;
+
+        let proc = PathBuf::from(format!("/proc/{}", self.pid));
+        let cwd = PathBuf::from(format!("/proc/{}/cwd", self.module_pid()?));
+        let cwd = CString::new(fs::read_link(cwd)?.as_os_str().as_bytes())?;
+
+        // The namespaces are opened upfront as the child must not allocate memory
+        let mut namespaces = Vec::new();
+        for (flag, name) in [
+            (CloneFlags::CLONE_NEWIPC, "ipc"),
+            (CloneFlags::CLONE_NEWNET, "net"),
+            (CloneFlags::CLONE_NEWPID, "pid_for_children"),
+            (CloneFlags::CLONE_NEWNS, "mnt"),
+        ] {
+            if self.opts.sandbox.namespaces.contains(flag) {
+                namespaces.push((flag, File::open(proc.join("ns").join(name))?));
+            }
+        }
+
+        let mut cmd = process::Command::new(program);
+        // The process of the module inherits the environment of hyperion as well
+        cmd.args(args)
+            .envs(self.opts.env.clone())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut opts = self.opts.clone();
+        // The cgroup is held on to by the context hence its file descriptor is valid
+        opts.cgroup = self.cgroup.as_deref().map(Cgroup::procs_fd);
+        // Safety: the hook runs in between fork and exec and only makes system calls
+        unsafe {
+            cmd.pre_exec(move || {
+                unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(errno)?;
+
+                if let Some(fd) = opts.cgroup {
+                    unistd::write(fd, b"0").map_err(errno)?;
+                }
+                opts.limits.apply_rlimits(opts.cgroup.is_some())?;
+
+                for (flag, ns) in &namespaces {
+                    setns(ns.as_raw_fd(), *flag).map_err(errno)?;
+                }
+                unistd::chdir(cwd.as_c_str()).map_err(errno)?;
+
+                // Joining a PID namespace only affects the children of the caller
+                if opts.sandbox.namespaces.contains(CloneFlags::CLONE_NEWPID) {
+                    Sandbox::fork_into_namespace()?;
+                }
+
+                opts.security.apply()?;
+                opts.sandbox.confine()
+            });
+        }
+
+        let mut child = orphans::spawn_child(&mut cmd)?;
+        let pid = child.id().unwrap_or_default();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            let mut stdout_buf = vec![0; 4096];
+            let mut stderr_buf = vec![0; 4096];
+            let (mut stdout_open, mut stderr_open) = (true, true);
+
+            while stdout_open || stderr_open {
+                let output = select! {
+                    res = stdout.read(&mut stdout_buf), if stdout_open => match res {
+                        Ok(n) if n > 0 => Some(Output::Stdout(stdout_buf[..n].to_vec())),
+                        _ => {
+                            stdout_open = false;
+                            None
+                        }
+                    },
+                    res = stderr.read(&mut stderr_buf), if stderr_open => match res {
+                        Ok(n) if n > 0 => Some(Output::Stderr(stderr_buf[..n].to_vec())),
+                        _ => {
+                            stderr_open = false;
+                            None
+                        }
+                    },
+                };
+
+                // Dropping the child kills the command once the caller stops listening
+                if let Some(output) = output {
+                    if tx.send(output).await.is_err() {
+                        drop(child);
+                        orphans::untrack_child(pid);
+                        return;
+                    }
+                }
+            }
+
+            match child.wait().await {
+                Ok(status) => {
+                    let _ = tx.send(Output::Exit(status)).await;
+                }
+                Err(err) => log::error!("failed to wait on command: {}", err),
+            }
+            orphans::untrack_child(pid);
+        });
+
+        Ok(rx)
+    }
+
+    /// module_pid returns the pid of the module itself, inside a PID namespace the pid
+    /// of the context is the relay which forked the init of the namespace and the
+    /// module is the first child of the init
+    fn module_pid(&self) -> Result<u32> {
+        if !self
+            .opts
+            .sandbox
+            .namespaces
+            .contains(CloneFlags::CLONE_NEWPID)
+        {
+            return Ok(self.pid);
+        }
+
+        let mut pid = self.pid;
+        for _ in 0..2 {
+            pid = fs::read_to_string(format!("/proc/{0}/task/{0}/children", pid))?
+                .split_whitespace()
+                .next()
+                .and_then(|child| child.parse().ok())
+                .ok_or_else(|| anyhow!("process {} has no children", pid))?;
+        }
+
+        Ok(pid)
+    }
+}
+
+fn errno(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exec() {
+        let ctx = Context::new(std::process::id(), Options::default(), None);
+
+        let mut rx = ctx
+            .exec(&[
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo out; echo err >&2; exit 3".to_string(),
+            ])
+            .unwrap();
+
+        let mut outputs = Vec::new();
+        while let Some(output) = rx.recv().await {
+            outputs.push(output);
+        }
+
+        assert!(outputs.contains(&Output::Stdout(b"out\n".to_vec())));
+        assert!(outputs.contains(&Output::Stderr(b"err\n".to_vec())));
+        assert!(matches!(outputs.last(), Some(Output::Exit(status)) if status.code() == Some(3)));
+        assert!(ctx.exec(&[]).is_err());
+    }
+}
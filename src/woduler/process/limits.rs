@@ -0,0 +1,347 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use nix::sys::{
+    resource::{setrlimit, Resource},
+    signal::Signal,
+};
+
+use super::pidfd::PidFd;
+use crate::proto::base;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// CGROUP_SUPERVISOR is the leaf of the cgroup of hyperion which hyperion itself moves
+/// into, a cgroup with processes in it cannot hand controllers down to its children
+const CGROUP_SUPERVISOR: &str = "supervisor";
+/// CGROUP_SLICE is the child of the cgroup of hyperion which the cgroups of the modules
+/// are created under
+const CGROUP_SLICE: &str = "wodules";
+const CGROUP_CONTROLLERS: &str = "+cpu +memory +pids";
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+lazy_static! {
+    /// SLICE is the path to the cgroup which the cgroups of the modules are created
+    /// under, it is set up once as hyperion moves itself into a leaf of its cgroup
+    static ref SLICE: std::result::Result<PathBuf, String> =
+        Cgroup::setup_slice().map_err(|err| err.to_string());
+}
+
+/// Limits are the resource limits of a module, `None` means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// memory_max is the maximum memory in bytes
+    pub memory_max: Option<u64>,
+    /// cpu_millis is the CPU quota in thousandths of a CPU
+    pub cpu_millis: Option<u64>,
+    /// cpu_weight is the relative share of CPU time, in the range 1-10000
+    pub cpu_weight: Option<u64>,
+    pub pids_max: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl Limits {
+    /// from_module returns the limits defined in the spec of the module, unset
+    /// or zero values are treated as unlimited
+    pub fn from_module(md: &base::Module) -> Self {
+        let resources = match &md.spec {
+            Some(base::ModuleSpec {
+                resources: Some(resources),
+                ..
+            }) => resources,
+            _ => return Self::default(),
+        };
+
+        let limit = |value: u64| if value > 0 { Some(value) } else { None };
+
+        Self {
+            memory_max: limit(resources.memory_max),
+            cpu_millis: limit(resources.cpu_millis),
+            cpu_weight: limit(resources.cpu_weight),
+            pids_max: limit(resources.pids_max),
+            open_files: limit(resources.open_files),
+        }
+    }
+
+    /// is_empty returns true if none of the resources are limited
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// check returns an error naming the limits which cannot be enforced, the CPU quota
+    /// and weight and the number of processes can only be enforced by a cgroup as
+    /// `RLIMIT_NPROC` counts all of the processes of the user instead
+    pub fn check(&self, cgroup: bool) -> Result<()> {
+        if cgroup {
+            return Ok(());
+        }
+
+        let unenforced: Vec<&str> = [
+            ("cpu_millis", self.cpu_millis),
+            ("cpu_weight", self.cpu_weight),
+            ("pids_max", self.pids_max),
+        ]
+        .iter()
+        .filter(|(_, limit)| limit.is_some())
+        .map(|(name, _)| *name)
+        .collect();
+
+        match unenforced.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "{} cannot be enforced without cgroups v2",
+                unenforced.join(", ")
+            )),
+        }
+    }
+
+    /// apply_rlimits limits the calling process using `setrlimit`
+    ///
+    /// Limits which can be enforced by the cgroup are skipped if `cgroup` is true, the
+    /// limits which only a cgroup can enforce must have passed `check`
+    ///
+    /// # Caveats
+    /// - apply_rlimits is called in between fork and exec hence it must not allocate
+    pub fn apply_rlimits(&self, cgroup: bool) -> io::Result<()> {
+        let set = |resource, value: Option<u64>| match value {
+            Some(value) => setrlimit(resource, Some(value), Some(value))
+                .map_err(|err| io::Error::from_raw_os_error(err as i32)),
+            None => Ok(()),
+        };
+
+        // cgroups have no equivalent for open files
+        set(Resource::RLIMIT_NOFILE, self.open_files)?;
+
+        if !cgroup {
+            set(Resource::RLIMIT_AS, self.memory_max)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Cgroup is a cgroup v2 group created for a module under the cgroup of hyperion, the
+/// group is removed once dropped
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    /// create takes in the name of a module and its limits and returns a cgroup
+    /// which enforces those limits
+    ///
+    /// An error is returned if cgroups v2 is not available on the host or the cgroup of
+    /// hyperion has not been delegated to it
+    pub fn create(name: &str, limits: &Limits) -> Result<Self> {
+        let slice = SLICE.as_ref().map_err(|err| anyhow!("{}", err))?;
+
+        let path = slice.join(name);
+        Self::create_dir(&path)?;
+
+        // A group left behind by an earlier run might carry stale limits hence
+        // unlimited resources are explicitly reset
+        let max = |value: Option<u64>| match value {
+            Some(value) => value.to_string(),
+            None => "max".to_string(),
+        };
+
+        fs::write(path.join("memory.max"), max(limits.memory_max))?;
+        fs::write(path.join("pids.max"), max(limits.pids_max))?;
+        fs::write(
+            path.join("cpu.max"),
+            format!(
+                "{} {}",
+                max(limits
+                    .cpu_millis
+                    .map(|millis| millis * CPU_PERIOD_MICROS / 1000)),
+                CPU_PERIOD_MICROS
+            ),
+        )?;
+        fs::write(
+            path.join("cpu.weight"),
+            limits.cpu_weight.unwrap_or(100).to_string(),
+        )?;
+
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))?;
+
+        Ok(Self { path, procs })
+    }
+
+    /// available returns true if the cgroups of the modules can be created
+    pub fn available() -> bool {
+        SLICE.is_ok()
+    }
+
+    /// procs_fd returns the file descriptor of `cgroup.procs` of the group, writing
+    /// "0" to it moves the writing process into the group
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// oom_kills returns the number of processes in the group which have been
+    /// killed by the OOM killer
+    pub fn oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events
+                    .lines()
+                    .find_map(|line| line.strip_prefix("oom_kill "))
+                    .and_then(|count| count.trim().parse().ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// is_populated returns true if there are any live processes in the group
+    pub fn is_populated(&self) -> bool {
+        fs::read_to_string(self.path.join("cgroup.events"))
+            .map(|events| events.lines().any(|line| line == "populated 1"))
+            .unwrap_or_default()
+    }
+
+    /// kill kills all of the processes in the group, kernels without `cgroup.kill`
+    /// have the processes killed one by one
+    pub fn kill(&self) -> io::Result<()> {
+        // Writing to a file which does not exist would create it instead
+        let kill = self.path.join("cgroup.kill");
+        match kill.exists() {
+            true => fs::write(kill, "1"),
+            false => self.signal(Signal::SIGKILL),
+        }
+    }
+
+    /// procs returns the pids of the processes in the group
+    pub fn procs(&self) -> io::Result<Vec<u32>> {
+        Ok(fs::read_to_string(self.path.join("cgroup.procs"))?
+            .lines()
+            .filter_map(|pid| pid.parse().ok())
+            .collect())
+    }
+
+    /// cpu_seconds returns the CPU time consumed by every process which has ever been
+    /// in the group
+    pub fn cpu_seconds(&self) -> io::Result<f64> {
+        fs::read_to_string(self.path.join("cpu.stat"))?
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|usec| usec.trim().parse::<u64>().ok())
+            .map(|usec| usec as f64 / 1e6)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed cpu.stat"))
+    }
+
+    /// signal sends the signal to every process in the cgroup, processes are signalled
+    /// via pidfds so that a process which exits meanwhile is not confused with another
+    /// one reusing its pid
+    pub fn signal(&self, sig: Signal) -> io::Result<()> {
+        for pid in self.procs()? {
+            if let Ok(pidfd) = PidFd::open(pid) {
+                let _ = pidfd.signal(sig);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// setup_slice creates the cgroup which the cgroups of the modules are created under
+    /// as a child of the cgroup hyperion runs in, the cgroup has to be delegated to
+    /// hyperion, e.g. with `Delegate=yes` in its systemd unit, as the controllers are
+    /// enabled for its children
+    ///
+    /// The cgroups outside of the one of hyperion are never modified
+    fn setup_slice() -> Result<PathBuf> {
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return Err(anyhow!("cgroups v2 is not mounted at {}", CGROUP_ROOT));
+        }
+
+        let own = fs::read_to_string("/proc/self/cgroup")?
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .map(|path| path.trim_start_matches('/').to_string())
+            .ok_or_else(|| anyhow!("hyperion is not in a cgroup v2 hierarchy"))?;
+
+        // An earlier run of hyperion might have moved into the leaf already
+        let own = root.join(own);
+        let own = match own.file_name() {
+            Some(leaf) if leaf == CGROUP_SUPERVISOR => own.parent().unwrap().to_path_buf(),
+            _ => own,
+        };
+        if own == root {
+            return Err(anyhow!(
+                "hyperion runs in the root cgroup - run it in a cgroup delegated to it"
+            ));
+        }
+
+        let supervisor = own.join(CGROUP_SUPERVISOR);
+        Self::create_dir(&supervisor)?;
+        fs::write(supervisor.join("cgroup.procs"), "0")?;
+
+        // Controllers have to be enabled at every level below the cgroup of hyperion
+        fs::write(own.join("cgroup.subtree_control"), CGROUP_CONTROLLERS)?;
+        let slice = own.join(CGROUP_SLICE);
+        Self::create_dir(&slice)?;
+        fs::write(slice.join("cgroup.subtree_control"), CGROUP_CONTROLLERS)?;
+
+        Ok(slice)
+    }
+
+    fn create_dir(path: &Path) -> io::Result<()> {
+        match fs::create_dir(path) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir(&self.path) {
+            log::warn!("failed to remove cgroup {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_module() {
+        assert!(Limits::from_module(&base::Module::default()).is_empty());
+
+        let md = base::Module {
+            spec: Some(base::ModuleSpec {
+                resources: Some(base::module_spec::Resources {
+                    memory_max: 64 << 20,
+                    cpu_millis: 500,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let limits = Limits::from_module(&md);
+        assert_eq!(limits.memory_max, Some(64 << 20));
+        assert_eq!(limits.cpu_millis, Some(500));
+        assert_eq!(limits.pids_max, None);
+        assert!(!limits.is_empty());
+
+        // Only the memory is limited without a cgroup
+        assert!(limits.check(true).is_ok());
+        assert!(limits.check(false).is_err());
+        let memory = Limits {
+            memory_max: Some(64 << 20),
+            ..Default::default()
+        };
+        assert!(memory.check(false).is_ok());
+    }
+}
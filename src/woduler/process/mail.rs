@@ -1,4 +1,5 @@
 use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::io::{AsyncBufRead, AsyncReadExt};
 
@@ -31,6 +32,25 @@ impl Mail {
         res
     }
 
+    /// payload returns the data carried by the mail, the data of a mail wrapped in an
+    /// `Envelope` is returned without the envelope
+    pub fn payload(&self) -> &[u8] {
+        if self.typ != data_type::ENVELOPE {
+            return &self.data;
+        }
+
+        // Skip the source and the topic followed by the sequence number and the timestamp
+        let mut offset = 0;
+        for _ in 0..2 {
+            match self.data.get(offset..offset + 2) {
+                Some(len) => offset += 2 + u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return &[],
+            }
+        }
+
+        self.data.get(offset + 16..).unwrap_or_default()
+    }
+
     pub async fn from_stream<T>(stream: &mut T, data: &mut Vec<u8>) -> Result<Self, std::io::Error>
     where
         T: AsyncBufRead + Unpin,
@@ -99,7 +119,133 @@ impl Mail {
     }
 }
 
+/// Envelope wraps the data forwarded to a process with the information about where
+/// the data came from, so that a process consuming data from several modules can
+/// demultiplex it.
+///
+/// Envelope is delivered to the process as a `Mail` of type `data_type::ENVELOPE` whose
+/// payload is laid out as (all of the integers are big endian):
+/// - 2 bytes length of the source module name followed by the name, the name is empty for
+///   the data published to the module from outside of Hyperion
+/// - 2 bytes length of the topic followed by the topic
+/// - 8 bytes sequence number of the message from the source, starting at 1
+/// - 8 bytes unix timestamp in milliseconds at which the message was forwarded
+/// - the data of the original mail
+pub struct Envelope {
+    pub source: String,
+    pub topic: String,
+    pub seq: u64,
+    pub timestamp: u64,
+    pub mail: Mail,
+}
+
+impl Envelope {
+    /// new wraps the mail in an envelope timestamped with the current time
+    pub fn new(source: String, topic: String, seq: u64, mail: Mail) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self {
+            source,
+            topic,
+            seq,
+            timestamp,
+            mail,
+        }
+    }
+
+    pub fn into_mail(self) -> Mail {
+        let mut data = Vec::with_capacity(
+            2 + self.source.len() + 2 + self.topic.len() + 8 + 8 + self.mail.data.len(),
+        );
+        data.extend((self.source.len() as u16).to_be_bytes());
+        data.extend(self.source.as_bytes());
+        data.extend((self.topic.len() as u16).to_be_bytes());
+        data.extend(self.topic.as_bytes());
+        data.extend(self.seq.to_be_bytes());
+        data.extend(self.timestamp.to_be_bytes());
+        data.extend(self.mail.data);
+
+        Mail {
+            typ: data_type::ENVELOPE,
+            size: data.len() as u64,
+            data,
+        }
+    }
+}
+
+/// DeadLetter is a message which could not be delivered to its subscriber along with the
+/// reason of the failure
+///
+/// DeadLetter is published to the dead letter topic as a `Mail` of type
+/// `data_type::DEAD_LETTER` whose payload is laid out as (all of the integers are big endian):
+/// - 2 bytes length of the reason followed by the reason
+/// - 2 bytes length of the topic on which the mail was published followed by the topic
+/// - 1 byte type of the original mail
+/// - the data of the original mail
+pub struct DeadLetter {
+    pub reason: String,
+    pub topic: String,
+    pub mail: Mail,
+}
+
+impl DeadLetter {
+    pub fn into_mail(self) -> Mail {
+        let mut data = Vec::with_capacity(
+            2 + self.reason.len() + 2 + self.topic.len() + 1 + self.mail.data.len(),
+        );
+        data.extend((self.reason.len() as u16).to_be_bytes());
+        data.extend(self.reason.as_bytes());
+        data.extend((self.topic.len() as u16).to_be_bytes());
+        data.extend(self.topic.as_bytes());
+        data.push(self.mail.typ);
+        data.extend(self.mail.data);
+
+        Mail {
+            typ: data_type::DEAD_LETTER,
+            size: data.len() as u64,
+            data,
+        }
+    }
+}
+
 pub mod data_type {
     pub const LOG: u8 = 0;
     pub const DATA: u8 = 1;
+    pub const ENVELOPE: u8 = 2;
+    pub const DEAD_LETTER: u8 = 3;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_into_mail() {
+        let envelope = Envelope {
+            source: "src".to_string(),
+            topic: "t".to_string(),
+            seq: 7,
+            timestamp: 9,
+            mail: Mail {
+                typ: data_type::DATA,
+                size: 2,
+                data: vec![0xaa, 0xbb],
+            },
+        };
+
+        let mail = envelope.into_mail();
+        assert_eq!(mail.typ, data_type::ENVELOPE);
+        assert_eq!(mail.size, mail.data.len() as u64);
+        assert_eq!(
+            mail.data,
+            vec![
+                0, 3, b's', b'r', b'c', 0, 1, b't', 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 9,
+                0xaa, 0xbb
+            ]
+        );
+        assert_eq!(mail.payload(), &[0xaa, 0xbb]);
+    }
 }
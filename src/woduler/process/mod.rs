@@ -1,7 +1,23 @@
+mod config;
 mod controller;
 mod core;
+pub(crate) mod exec;
+mod limits;
 pub(crate) mod mail;
+mod orphans;
+mod pidfd;
+mod pty;
+mod sandbox;
+mod security;
+mod usage;
 
 pub use self::core::*;
+pub use config::*;
 pub use controller::*;
+pub use limits::*;
 pub use mail::*;
+pub use orphans::*;
+pub use pty::*;
+pub use sandbox::*;
+pub use security::*;
+pub use usage::*;
@@ -0,0 +1,375 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use nix::{
+    sys::{
+        signal::{self, Signal},
+        wait::{waitpid, WaitPidFlag},
+    },
+    unistd::Pid,
+};
+use tokio::{
+    process,
+    signal::unix::{signal as unix_signal, SignalKind},
+    sync::Mutex,
+};
+
+use super::{pidfd::PidFd, usage::Stat};
+
+/// Time given to a survivor to exit after `SIGTERM` before it is killed
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    /// CHILDREN are the pids of the processes spawned and reaped by `Process`, the
+    /// subreaper must leave them alone
+    static ref CHILDREN: std::sync::Mutex<HashSet<u32>> = std::sync::Mutex::new(HashSet::new());
+    /// SPAWNING is held for reading while a child is spawned until its pid is marked,
+    /// the subreaper only reaps while it can take it for writing
+    static ref SPAWNING: std::sync::RwLock<()> = std::sync::RwLock::new(());
+}
+
+/// SUBREAPER is set once hyperion has become the subreaper of its descendants
+static SUBREAPER: AtomicBool = AtomicBool::new(false);
+
+/// Policy decides what happens to the wodules which survived a crash of hyperion
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Terminate stops the survivors on startup
+    Terminate,
+    /// Adopt keeps the survivors running until the module is applied again, module
+    /// definitions are not kept across runs of hyperion hence the survivors are not
+    /// managed meanwhile, they are neither restarted, signalled nor reported
+    Adopt,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "terminate" => Ok(Self::Terminate),
+            "adopt" => Ok(Self::Adopt),
+            _ => Err(anyhow!(
+                "invalid orphan policy \"{}\" - expected \"terminate\" or \"adopt\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Orphans keeps a record of the running wodules in the state directory so that the
+/// wodules which survive a crash of hyperion can be found by the next run
+#[derive(Clone)]
+pub struct Orphans {
+    dir: PathBuf,
+    adopted: Arc<Mutex<HashMap<String, Vec<Arc<PidFd>>>>>,
+}
+
+impl Orphans {
+    /// new takes in the state directory of hyperion and returns an instance of Orphans
+    pub fn new(state_dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(state_dir).join("wodules"),
+            adopted: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// recover finds the wodules left behind by an earlier run of hyperion and deals
+    /// with them as per the policy
+    pub async fn recover(&self, policy: Policy) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let survivor = Self::parse_record(&fs::read_to_string(entry.path())?).and_then(
+                |(name, pid, start_time)| {
+                    // The pid might have been recycled by an unrelated process
+                    match Stat::read(pid) {
+                        Ok(stat) if stat.start_time == start_time && stat.state != 'Z' => {
+                            Some((name, pid))
+                        }
+                        _ => None,
+                    }
+                },
+            );
+
+            let (name, pid) = match survivor {
+                Some(survivor) => survivor,
+                None => {
+                    let _ = fs::remove_file(entry.path());
+                    continue;
+                }
+            };
+
+            let pidfd = match PidFd::open(pid) {
+                Ok(pidfd) => Arc::new(pidfd),
+                Err(_) => {
+                    let _ = fs::remove_file(entry.path());
+                    continue;
+                }
+            };
+
+            match policy {
+                Policy::Terminate => {
+                    log::info!("terminating wodule: {} left behind with pid: {}", name, pid);
+
+                    Self::terminate(&pidfd).await;
+                    self.forget(pid);
+                }
+                Policy::Adopt => {
+                    log::info!("adopting wodule: {} left behind with pid: {}", name, pid);
+
+                    self.adopt(name, pidfd).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// record saves the pid of the process of the module to the state directory
+    pub fn record(&self, name: &str, pid: u32) {
+        let res = Stat::read(pid).and_then(|stat| {
+            fs::create_dir_all(&self.dir)?;
+            fs::write(
+                self.dir.join(pid.to_string()),
+                format!("{} {} {}\n", name, pid, stat.start_time),
+            )?;
+
+            Ok(())
+        });
+
+        if let Err(err) = res {
+            log::warn!(
+                "failed to record pid: {} of wodule: {} - {}",
+                pid,
+                name,
+                err
+            );
+        }
+    }
+
+    /// forget removes the record of the process from the state directory
+    pub fn forget(&self, pid: u32) {
+        let _ = fs::remove_file(self.dir.join(pid.to_string()));
+    }
+
+    /// release terminates the adopted processes of the module so that the module can
+    /// be started again without running twice, the runs of a scheduled module are
+    /// recorded as `<name>.<uuid>` and released along with it
+    pub async fn release(&self, name: &str) {
+        let survivors: Vec<_> = {
+            let mut adopted = self.adopted.lock().await;
+            let names: Vec<_> = adopted
+                .keys()
+                .filter(|adopted| {
+                    *adopted == name
+                        || matches!(adopted.strip_prefix(name), Some(run) if run.starts_with('.'))
+                })
+                .cloned()
+                .collect();
+
+            names
+                .iter()
+                .filter_map(|name| adopted.remove(name))
+                .flatten()
+                .collect()
+        };
+
+        for pidfd in survivors {
+            log::info!(
+                "terminating adopted wodule: {} with pid: {}",
+                name,
+                pidfd.pid()
+            );
+
+            Self::terminate(&pidfd).await;
+            self.forget(pidfd.pid());
+        }
+    }
+
+    /// adopt keeps track of a survivor until it exits
+    async fn adopt(&self, name: String, pidfd: Arc<PidFd>) {
+        self.adopted
+            .lock()
+            .await
+            .entry(name.clone())
+            .or_default()
+            .push(Arc::clone(&pidfd));
+
+        let orphans = self.clone();
+        tokio::spawn(async move {
+            pidfd.exited().await;
+
+            log::info!("adopted wodule: {} with pid: {} exited", name, pidfd.pid());
+
+            orphans.forget(pidfd.pid());
+            if let Some(survivors) = orphans.adopted.lock().await.get_mut(&name) {
+                survivors.retain(|survivor| !Arc::ptr_eq(survivor, &pidfd));
+            }
+        });
+    }
+
+    /// terminate stops the survivor along with its process group
+    async fn terminate(pidfd: &PidFd) {
+        // Wodules lead their own process group
+        let pgid = Pid::from_raw(pidfd.pid() as i32);
+
+        if pidfd.signal(Signal::SIGTERM).is_ok() {
+            let _ = signal::killpg(pgid, Signal::SIGTERM);
+        }
+
+        if tokio::time::timeout(TERMINATE_GRACE, pidfd.exited())
+            .await
+            .is_err()
+        {
+            let _ = pidfd.signal(Signal::SIGKILL);
+            let _ = signal::killpg(pgid, Signal::SIGKILL);
+            pidfd.exited().await;
+        }
+    }
+
+    fn parse_record(record: &str) -> Option<(String, u32, u64)> {
+        let mut fields = record.split_whitespace();
+
+        let name = fields.next()?.to_string();
+        let pid = fields.next()?.parse().ok()?;
+        let start_time = fields.next()?.parse().ok()?;
+
+        Some((name, pid, start_time))
+    }
+}
+
+/// spawn_child spawns the command and marks its pid as a child which is reaped by
+/// its owner, the subreaper is held off from before the spawn until the pid is marked
+/// so that a child which exits right away is not reaped behind the back of its owner
+///
+/// Spawns do not wait on each other, the subreaper skips a round while any is going on
+pub(super) fn spawn_child(cmd: &mut process::Command) -> io::Result<process::Child> {
+    let child = {
+        let _spawning = SPAWNING.read().unwrap();
+
+        let child = cmd.spawn()?;
+        if let Some(pid) = child.id() {
+            CHILDREN.lock().unwrap().insert(pid);
+        }
+
+        child
+    };
+
+    // The orphans which exited during the spawn were left alone
+    if SUBREAPER.load(Ordering::Relaxed) {
+        reap_orphans();
+    }
+
+    Ok(child)
+}
+
+/// untrack_child unmarks the pid once its owner has reaped it or has given up on it,
+/// the orphans which exited meanwhile are reaped
+pub(super) fn untrack_child(pid: u32) {
+    CHILDREN.lock().unwrap().remove(&pid);
+
+    if SUBREAPER.load(Ordering::Relaxed) {
+        reap_orphans();
+    }
+}
+
+/// subreaper makes hyperion the subreaper of its descendants so that the processes
+/// orphaned by a wodule are reparented to hyperion rather than init, the orphans are
+/// reaped as they exit
+pub fn subreaper() -> Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        return Err(anyhow!(
+            "failed to become a subreaper: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    SUBREAPER.store(true, Ordering::Relaxed);
+
+    let mut sigchld = unix_signal(SignalKind::child())?;
+    tokio::spawn(async move {
+        while sigchld.recv().await.is_some() {
+            reap_orphans();
+        }
+    });
+
+    Ok(())
+}
+
+/// reap_orphans reaps the exited children of hyperion which are not owned by anyone
+fn reap_orphans() {
+    // No child is spawned while the lock is held hence every child which is not marked
+    // is an orphan, the spawns going on reap the orphans once they are done
+    let _spawning = match SPAWNING.try_write() {
+        Ok(spawning) => spawning,
+        Err(_) => return,
+    };
+    let children = CHILDREN.lock().unwrap();
+
+    loop {
+        // Peek at an exited child without reaping it
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::waitid(
+                libc::P_ALL,
+                0,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        let pid = unsafe { info.si_pid() };
+        if res != 0 || pid == 0 {
+            return;
+        }
+
+        // The kernel keeps handing out the same child until it is reaped, the orphans
+        // behind an owned child are reaped once its owner untracks it
+        if children.contains(&(pid as u32)) {
+            return;
+        }
+
+        log::debug!("reaping orphaned process: {}", pid);
+
+        let _ = waitpid(Pid::from_raw(pid), Some(WaitPidFlag::WNOHANG));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recover() {
+        let dir = std::env::temp_dir().join(format!("hyperion-orphans-{}", std::process::id()));
+        let orphans = Orphans::new(dir.to_str().unwrap());
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("100")
+            .spawn()
+            .unwrap();
+        orphans.record("sleeper", child.id());
+        // A record of a process which has been replaced by another one
+        fs::write(orphans.dir.join("1"), "stale 1 0\n").unwrap();
+
+        orphans.recover(Policy::Adopt).await.unwrap();
+        assert_eq!(orphans.adopted.lock().await["sleeper"].len(), 1);
+        assert!(!orphans.dir.join("1").exists());
+
+        orphans.release("sleeper").await;
+        assert!(!child.wait().unwrap().success());
+        assert!(!orphans.dir.join(child.id().to_string()).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
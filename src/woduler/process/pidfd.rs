@@ -0,0 +1,134 @@
+use std::{
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::io::unix::AsyncFd;
+
+use super::usage::Stat;
+
+/// Interval at which a process is polled on kernels without pidfd support
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// PidFd is a handle to a process which, unlike a pid, cannot end up referring to
+/// another process once the process dies and its pid is recycled
+///
+/// Kernels older than 5.3 lack pidfd support in which case the handle falls back
+/// to using the pid
+pub struct PidFd {
+    pid: Pid,
+    fd: Option<AsyncFd<OwnedFd>>,
+}
+
+impl PidFd {
+    /// open takes in a pid and returns a handle to the process, an error is returned
+    /// if the process does not exist
+    pub fn open(pid: u32) -> io::Result<Self> {
+        let raw = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if raw < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ENOSYS) {
+                return Err(err);
+            }
+
+            log::debug!(
+                "pidfd is not supported - falling back to polling pid: {}",
+                pid
+            );
+
+            return Ok(Self {
+                pid: Pid::from_raw(pid as libc::pid_t),
+                fd: None,
+            });
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(raw as libc::c_int) };
+
+        Ok(Self {
+            pid: Pid::from_raw(pid as libc::pid_t),
+            fd: Some(AsyncFd::new(fd)?),
+        })
+    }
+
+    /// pid returns the pid of the process
+    pub fn pid(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
+    /// exited resolves once the process has exited, it does not reap the process
+    pub async fn exited(&self) {
+        match &self.fd {
+            // A pidfd becomes readable once the process exits
+            Some(fd) => {
+                if let Err(err) = fd.readable().await {
+                    log::error!("failed to wait on pidfd of pid: {} - {}", self.pid, err);
+                }
+            }
+            // A zombie still accepts signals hence its state is checked as well
+            None => {
+                while signal::kill(self.pid, None).is_ok()
+                    && !matches!(Stat::read(self.pid()), Ok(stat) if stat.state == 'Z')
+                {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// signal sends the signal to the process, `None` only checks whether the process
+    /// is still alive
+    pub fn signal<T: Into<Option<Signal>>>(&self, sig: T) -> io::Result<()> {
+        let sig = sig.into();
+
+        match &self.fd {
+            Some(fd) => {
+                let res = unsafe {
+                    libc::syscall(
+                        libc::SYS_pidfd_send_signal,
+                        fd.get_ref().as_raw_fd(),
+                        sig.map(|sig| sig as libc::c_int).unwrap_or_default(),
+                        std::ptr::null::<libc::siginfo_t>(),
+                        0,
+                    )
+                };
+                if res < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(())
+            }
+            None => {
+                signal::kill(self.pid, sig).map_err(|err| io::Error::from_raw_os_error(err as i32))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pidfd() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("100")
+            .spawn()
+            .unwrap();
+        let pidfd = PidFd::open(child.id()).unwrap();
+
+        assert!(pidfd.signal(None).is_ok());
+        pidfd.signal(Signal::SIGTERM).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), pidfd.exited())
+            .await
+            .unwrap();
+
+        assert!(!child.wait().unwrap().success());
+        // The pid of a reaped process is never signalled
+        assert!(pidfd.signal(Signal::SIGKILL).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    sync::Arc,
+};
+
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    pty::{openpty, Winsize},
+};
+use tokio::{
+    io::unix::AsyncFd,
+    sync::{broadcast, mpsc},
+};
+
+use super::mail::{self, Mail};
+
+/// Number of output chunks buffered for an attached client which falls behind
+const OUTPUT_BUFFER: usize = 64;
+
+/// Pty is the master side of a pseudo-terminal
+pub struct Pty {
+    master: AsyncFd<File>,
+}
+
+impl Pty {
+    /// open returns a new pseudo-terminal along with its slave side which is meant to
+    /// be handed to the child process
+    pub fn open() -> io::Result<(Self, File)> {
+        let res = openpty(None, None).map_err(errno)?;
+        let master = unsafe { File::from_raw_fd(res.master) };
+        let slave = unsafe { File::from_raw_fd(res.slave) };
+
+        // Neither side is leaked into the child, the slave reaches it via its stdio
+        for fd in [&master, &slave] {
+            fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(errno)?;
+        }
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(errno)?;
+
+        Ok((
+            Self {
+                master: AsyncFd::new(master)?,
+            },
+            slave,
+        ))
+    }
+
+    /// read reads the output of the terminal, `0` is returned once the slave side is
+    /// closed by all of the processes
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.master.readable().await?;
+
+            match guard.try_io(|fd| fd.get_ref().read(buf)) {
+                // Linux reports a hung up terminal with EIO
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(res) => return res,
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// write_all writes the data to the terminal as if it was typed in
+    pub async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.master.writable().await?;
+
+            if let Ok(res) = guard.try_io(|fd| fd.get_ref().write(data)) {
+                data = &data[res?..];
+            }
+        }
+
+        Ok(())
+    }
+
+    /// resize sets the window size of the terminal which notifies the foreground
+    /// process group with `SIGWINCH`
+    pub fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
+        let size = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Input is what an attached client sends to the terminal
+pub enum Input {
+    Data(Vec<u8>),
+    Resize { rows: u16, cols: u16 },
+}
+
+/// Terminal lets clients attach to the pseudo-terminal of a process, the raw output
+/// of the terminal is fanned out to all of the attached clients
+#[derive(Clone)]
+pub struct Terminal {
+    output: broadcast::Sender<Vec<u8>>,
+    input: mpsc::Sender<Input>,
+}
+
+impl Terminal {
+    /// new takes in the master side of a pseudo-terminal and the `mailbox` which the
+    /// output is also sent to as logs, it returns a terminal ready to be attached to
+    pub fn new(pty: Pty, logs: mpsc::Sender<Mail>) -> Self {
+        let pty = Arc::new(pty);
+        let (output, _) = broadcast::channel(OUTPUT_BUFFER);
+        let (input, mut input_rx) = mpsc::channel(8);
+
+        let reader = Arc::clone(&pty);
+        let tx = output.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 4096];
+
+            loop {
+                let n = match reader.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) => {
+                        log::error!("failed to read from terminal: {}", err);
+                        break;
+                    }
+                };
+
+                // No attached clients is not an error
+                let _ = tx.send(buf[..n].to_vec());
+
+                let mail = Mail {
+                    typ: mail::data_type::LOG,
+                    size: n as u64,
+                    data: buf[..n].to_vec(),
+                };
+                let _ = logs.send(mail).await;
+            }
+
+            log::debug!("terminal closed");
+        });
+
+        tokio::spawn(async move {
+            while let Some(input) = input_rx.recv().await {
+                let res = match input {
+                    Input::Data(data) => pty.write_all(&data).await,
+                    Input::Resize { rows, cols } => pty.resize(rows, cols),
+                };
+
+                if let Err(err) = res {
+                    log::debug!("failed to write to terminal: {}", err);
+                }
+            }
+        });
+
+        Self { output, input }
+    }
+
+    /// attach returns a receiver of the output of the terminal and a sender for the
+    /// input of the terminal
+    pub fn attach(&self) -> (broadcast::Receiver<Vec<u8>>, mpsc::Sender<Input>) {
+        (self.output.subscribe(), self.input.clone())
+    }
+}
+
+fn errno(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_terminal() {
+        let (pty, slave) = Pty::open().unwrap();
+        let (logs, mut logs_rx) = mpsc::channel(8);
+        let terminal = Terminal::new(pty, logs);
+        let (mut output, input) = terminal.attach();
+
+        let mut child = std::process::Command::new("cat")
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave)
+            .spawn()
+            .unwrap();
+
+        input.send(Input::Data(b"ping\n".to_vec())).await.unwrap();
+
+        // The terminal echoes the input and cat writes it back
+        let mut seen = Vec::new();
+        while !String::from_utf8_lossy(&seen).contains("ping\r\nping\r\n") {
+            seen.extend(output.recv().await.unwrap());
+        }
+        assert_eq!(logs_rx.recv().await.unwrap().typ, mail::data_type::LOG);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
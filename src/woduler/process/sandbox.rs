@@ -0,0 +1,639 @@
+use std::io;
+
+use anyhow::{anyhow, Result};
+use nix::{
+    mount::{mount, MsFlags},
+    sched::{unshare, CloneFlags},
+    sys::{
+        signal::{self, kill, sigprocmask, SigHandler, SigSet, SigmaskHow, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{fork, getpid, isatty, setpgid, tcsetpgrp, ForkResult, Pid},
+};
+
+use crate::proto::base;
+
+/// Signals which are relayed by the init process of a PID namespace to the module
+pub(super) const FORWARDED_SIGNALS: [Signal; 6] = [
+    Signal::SIGINT,
+    Signal::SIGTERM,
+    Signal::SIGHUP,
+    Signal::SIGQUIT,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls denied by the default seccomp profile
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_acct,
+    libc::SYS_add_key,
+    libc::SYS_adjtimex,
+    libc::SYS_clock_adjtime,
+    libc::SYS_clock_settime,
+    libc::SYS_delete_module,
+    libc::SYS_finit_module,
+    libc::SYS_fsmount,
+    libc::SYS_fsopen,
+    libc::SYS_init_module,
+    libc::SYS_kexec_file_load,
+    libc::SYS_kexec_load,
+    libc::SYS_keyctl,
+    libc::SYS_lookup_dcookie,
+    libc::SYS_mount,
+    libc::SYS_move_mount,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_open_tree,
+    libc::SYS_pivot_root,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_ptrace,
+    libc::SYS_quotactl,
+    libc::SYS_reboot,
+    libc::SYS_request_key,
+    libc::SYS_setns,
+    libc::SYS_settimeofday,
+    libc::SYS_swapoff,
+    libc::SYS_swapon,
+    libc::SYS_syslog,
+    libc::SYS_umount2,
+    libc::SYS_unshare,
+    libc::SYS_userfaultfd,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_uselib,
+];
+
+/// Syscalls allowed by the strict seccomp profile, enough for a statically or
+/// dynamically linked program doing IO on the file descriptors it is given
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_brk,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_close,
+    libc::SYS_dup3,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_execve,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_fcntl,
+    libc::SYS_fstat,
+    libc::SYS_futex,
+    libc::SYS_getcwd,
+    libc::SYS_getegid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getpid,
+    libc::SYS_getppid,
+    libc::SYS_getrandom,
+    libc::SYS_gettid,
+    libc::SYS_gettimeofday,
+    libc::SYS_getuid,
+    libc::SYS_lseek,
+    libc::SYS_madvise,
+    libc::SYS_mmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_munmap,
+    libc::SYS_nanosleep,
+    libc::SYS_newfstatat,
+    libc::SYS_openat,
+    libc::SYS_pipe2,
+    libc::SYS_ppoll,
+    libc::SYS_pread64,
+    libc::SYS_prlimit64,
+    libc::SYS_pwrite64,
+    libc::SYS_read,
+    libc::SYS_readlinkat,
+    libc::SYS_readv,
+    libc::SYS_rseq,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_yield,
+    libc::SYS_set_robust_list,
+    libc::SYS_set_tid_address,
+    libc::SYS_sigaltstack,
+    libc::SYS_statx,
+    libc::SYS_tgkill,
+    libc::SYS_uname,
+    libc::SYS_wait4,
+    libc::SYS_write,
+    libc::SYS_writev,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_pipe,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+];
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Profile is a seccomp profile which a module can be confined with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// Unconfined does not filter any syscalls
+    Unconfined,
+    /// Default denies the syscalls which can be used to tamper with the host
+    Default,
+    /// Strict allows only the syscalls needed for basic computation and IO
+    Strict,
+}
+
+impl Profile {
+    /// parse takes in the name of a profile, an empty name is treated as unconfined
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "" | "unconfined" => Ok(Self::Unconfined),
+            "default" => Ok(Self::Default),
+            "strict" => Ok(Self::Strict),
+            _ => Err(anyhow!(
+                "unknown seccomp profile \"{}\" - supported profiles: \"default\", \"strict\", \"unconfined\"",
+                name
+            )),
+        }
+    }
+
+    /// filter returns the BPF program which implements the profile, `None` is
+    /// returned for an unconfined profile
+    pub fn filter(&self) -> Result<Option<Vec<libc::sock_filter>>> {
+        let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+        let (syscalls, matched, fallback) = match self {
+            Self::Unconfined => return Ok(None),
+            Self::Default => (DENIED_SYSCALLS, deny, libc::SECCOMP_RET_ALLOW),
+            Self::Strict => (ALLOWED_SYSCALLS, libc::SECCOMP_RET_ALLOW, deny),
+        };
+
+        let arch = AUDIT_ARCH.ok_or_else(|| {
+            anyhow!(
+                "seccomp profiles are not supported on {}",
+                std::env::consts::ARCH
+            )
+        })?;
+
+        let stmt = |code: u32, k: u32| libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        };
+
+        let mut filter = vec![
+            // Syscall numbers differ across architectures hence a foreign one is killed
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, arch, 1, 0),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0),
+            // Deny the x32 ABI which would otherwise bypass the syscall numbers below
+            jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                0x4000_0000,
+                0,
+                1,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, deny),
+        ];
+
+        for nr in syscalls {
+            filter.push(jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                *nr as u32,
+                0,
+                1,
+            ));
+            filter.push(stmt(libc::BPF_RET | libc::BPF_K, matched));
+        }
+        filter.push(stmt(libc::BPF_RET | libc::BPF_K, fallback));
+
+        Ok(Some(filter))
+    }
+}
+
+/// Mount is a path of the host which is bind mounted into the sandbox
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+    pub read_only: bool,
+}
+
+/// Sandbox is the isolation which a module is executed in
+#[derive(Clone)]
+pub struct Sandbox {
+    pub namespaces: CloneFlags,
+    pub read_only_root: bool,
+    pub mounts: Vec<Mount>,
+    pub seccomp: Option<Vec<libc::sock_filter>>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            namespaces: CloneFlags::empty(),
+            read_only_root: false,
+            mounts: Vec::new(),
+            seccomp: None,
+        }
+    }
+}
+
+impl Sandbox {
+    /// from_module returns the sandbox defined in the spec of the module, a module
+    /// without a sandbox is not isolated
+    pub fn from_module(md: &base::Module) -> Result<Self> {
+        let sandbox = match &md.spec {
+            Some(base::ModuleSpec {
+                sandbox: Some(sandbox),
+                ..
+            }) => sandbox,
+            _ => return Ok(Self::default()),
+        };
+
+        let mut namespaces = CloneFlags::empty();
+        for ns in sandbox.namespaces.iter() {
+            namespaces |= match ns.as_str() {
+                "mount" => CloneFlags::CLONE_NEWNS,
+                "pid" => CloneFlags::CLONE_NEWPID,
+                "network" => CloneFlags::CLONE_NEWNET,
+                "ipc" => CloneFlags::CLONE_NEWIPC,
+                _ => {
+                    return Err(anyhow!(
+                        "unknown namespace \"{}\" - supported namespaces: \"mount\", \"pid\", \"network\", \"ipc\"",
+                        ns
+                    ))
+                }
+            };
+        }
+
+        let mounts = sandbox
+            .mounts
+            .iter()
+            .map(|m| {
+                if !m.source.starts_with('/') || !m.target.starts_with('/') {
+                    return Err(anyhow!(
+                        "mount \"{}\" => \"{}\" must use absolute paths",
+                        m.source,
+                        m.target
+                    ));
+                }
+
+                Ok(Mount {
+                    source: m.source.clone(),
+                    target: m.target.clone(),
+                    read_only: m.read_only,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if (sandbox.read_only_root || !mounts.is_empty())
+            && !namespaces.contains(CloneFlags::CLONE_NEWNS)
+        {
+            return Err(anyhow!(
+                "a read only root and bind mounts require the \"mount\" namespace"
+            ));
+        }
+
+        Ok(Self {
+            namespaces,
+            read_only_root: sandbox.read_only_root,
+            mounts,
+            seccomp: Profile::parse(&sandbox.seccomp)?.filter()?,
+        })
+    }
+
+    /// isolate moves the calling process into new namespaces and sets up its
+    /// filesystem
+    ///
+    /// With a new PID namespace the calling process stays behind as the parent of
+    /// the namespace, relaying signals to the module and exiting with its status
+    ///
+    /// # Caveats
+    /// - isolate is called in between fork and exec hence it must not allocate
+    pub fn isolate(&self) -> io::Result<()> {
+        if self.namespaces.is_empty() {
+            return Ok(());
+        }
+
+        unshare(self.namespaces).map_err(errno)?;
+
+        if self.namespaces.contains(CloneFlags::CLONE_NEWPID) {
+            Self::fork_into_namespace()?;
+        }
+
+        if !self.namespaces.contains(CloneFlags::CLONE_NEWNS) {
+            return Ok(());
+        }
+
+        // Stop the mounts below from propagating back to the host
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .map_err(errno)?;
+
+        if self.namespaces.contains(CloneFlags::CLONE_NEWPID) {
+            mount(
+                Some("proc"),
+                "/proc",
+                Some("proc"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                None::<&str>,
+            )
+            .map_err(errno)?;
+        }
+
+        if self.read_only_root {
+            Self::read_only_root()?;
+        }
+
+        for m in self.mounts.iter() {
+            mount(
+                Some(m.source.as_str()),
+                m.target.as_str(),
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REC,
+                None::<&str>,
+            )
+            .map_err(errno)?;
+
+            // A bind mount inherits the flags of the source hence the mount is
+            // remounted to be read only or writable as requested
+            let mut flags = MsFlags::MS_REMOUNT | MsFlags::MS_BIND;
+            if m.read_only {
+                flags |= MsFlags::MS_RDONLY;
+            }
+            mount(
+                None::<&str>,
+                m.target.as_str(),
+                None::<&str>,
+                flags,
+                None::<&str>,
+            )
+            .map_err(errno)?;
+        }
+
+        Ok(())
+    }
+
+    /// confine installs the seccomp filter, it must be called last as the filter
+    /// might deny the syscalls needed to set up the process
+    ///
+    /// # Caveats
+    /// - confine is called in between fork and exec hence it must not allocate
+    pub fn confine(&self) -> io::Result<()> {
+        let filter = match &self.seccomp {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+
+        let prog = libc::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_ptr() as *mut libc::sock_filter,
+        };
+
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        check(unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            )
+        })
+    }
+
+    /// fork_into_namespace forks the first process of the new PID namespace, the
+    /// process which returns goes on to exec the module while its parents relay the
+    /// signals to it and never return
+    pub(super) fn fork_into_namespace() -> io::Result<()> {
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGCHLD);
+        for sig in FORWARDED_SIGNALS {
+            mask.add(sig);
+        }
+
+        // Signals are blocked before forking so that none of them are missed
+        let mut old = SigSet::empty();
+        sigprocmask(SigmaskHow::SIG_BLOCK, Some(&mask), Some(&mut old)).map_err(errno)?;
+
+        match unsafe { fork() }.map_err(errno)? {
+            ForkResult::Child => {
+                // Signals sent to the process group of the parent are relayed, leaving
+                // the group keeps them from being delivered twice
+                setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(errno)?;
+
+                // The init of a PID namespace ignores the signals it has no handler
+                // for, hence it stays behind to relay the signals and to reap the
+                // orphans of the namespace while the module runs as its child
+                if getpid().as_raw() == 1 {
+                    check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) })?;
+
+                    if let ForkResult::Parent { child } = unsafe { fork() }.map_err(errno)? {
+                        Self::relay(child, &mask);
+                    }
+                    setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(errno)?;
+                }
+
+                // The terminal of the parent, if any, is handed over to the new group
+                // so that it is not stopped when using it
+                if isatty(0).unwrap_or(false) {
+                    let mut ttou = SigSet::empty();
+                    ttou.add(Signal::SIGTTOU);
+                    sigprocmask(SigmaskHow::SIG_BLOCK, Some(&ttou), None).map_err(errno)?;
+                    let _ = tcsetpgrp(0, getpid());
+                }
+
+                sigprocmask(SigmaskHow::SIG_SETMASK, Some(&old), None).map_err(errno)?;
+
+                // Take down the process if hyperion kills the parent
+                check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) })
+            }
+            ForkResult::Parent { child } => Self::relay(child, &mask),
+        }
+    }
+
+    /// relay forwards the signals to the child and exits with its status once it dies,
+    /// any other child is reaped as it exits
+    fn relay(child: Pid, mask: &SigSet) -> ! {
+        // Release the file descriptors inherited from hyperion, in particular the pipe
+        // which hyperion watches to find out whether exec has happened
+        if unsafe { libc::syscall(libc::SYS_close_range, 3, u32::MAX, 0) } != 0 {
+            for fd in 3..1024 {
+                unsafe { libc::close(fd) };
+            }
+        }
+
+        loop {
+            match mask.wait() {
+                Ok(Signal::SIGCHLD) => loop {
+                    match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                        Ok(WaitStatus::Exited(pid, code)) if pid == child => unsafe {
+                            libc::_exit(code)
+                        },
+                        Ok(WaitStatus::Signaled(pid, sig, _)) if pid == child => {
+                            // Die the same way the child did, the init of a PID
+                            // namespace cannot hence it exits with the usual code
+                            let mut unblock = SigSet::empty();
+                            unblock.add(sig);
+                            unsafe {
+                                let _ = signal::signal(sig, SigHandler::SigDfl);
+                            }
+                            let _ = sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&unblock), None);
+                            let _ = kill(getpid(), sig);
+
+                            unsafe { libc::_exit(128 + sig as i32) }
+                        }
+                        Ok(WaitStatus::StillAlive) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                },
+                Ok(sig) => {
+                    let _ = kill(child, sig);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// read_only_root makes every mount of the filesystem read only, older kernels
+    /// without `mount_setattr` only get the root mount remounted
+    fn read_only_root() -> io::Result<()> {
+        let attr = MountAttr {
+            attr_set: MOUNT_ATTR_RDONLY,
+            attr_clr: 0,
+            propagation: 0,
+            userns_fd: 0,
+        };
+
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                c"/".as_ptr(),
+                libc::AT_RECURSIVE,
+                &attr as *const MountAttr,
+                std::mem::size_of::<MountAttr>(),
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+
+        if io::Error::last_os_error().raw_os_error() != Some(libc::ENOSYS) {
+            return Err(io::Error::last_os_error());
+        }
+
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+            None::<&str>,
+        )
+        .map_err(errno)
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn errno(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_module() {
+        let module = |sandbox| base::Module {
+            spec: Some(base::ModuleSpec {
+                sandbox: Some(sandbox),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let sandbox = Sandbox::from_module(&module(base::module_spec::Sandbox {
+            namespaces: vec!["mount".into(), "network".into()],
+            read_only_root: true,
+            seccomp: "strict".into(),
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(
+            sandbox.namespaces,
+            CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWNET
+        );
+        assert_eq!(
+            sandbox.seccomp.map(|filter| filter.len()),
+            Some(7 + 2 * ALLOWED_SYSCALLS.len())
+        );
+
+        assert!(Sandbox::from_module(&module(base::module_spec::Sandbox {
+            read_only_root: true,
+            ..Default::default()
+        }))
+        .is_err());
+        assert!(Sandbox::from_module(&module(base::module_spec::Sandbox {
+            namespaces: vec!["user".into()],
+            ..Default::default()
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use std::io;
+
+use anyhow::{anyhow, Result};
+use nix::unistd::{self, Gid, Group, Uid, User};
+
+use crate::proto::base;
+
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Security is the identity and the privileges which a module is executed with
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Security {
+    pub uid: Option<Uid>,
+    pub gid: Option<Gid>,
+    /// capabilities is the bitmask of the capabilities retained by the process,
+    /// `None` means that the privileges of hyperion are inherited
+    pub capabilities: Option<u64>,
+}
+
+impl Security {
+    /// from_module resolves the security context defined in the spec of the module
+    ///
+    /// A module without a security context inherits the privileges of hyperion, once
+    /// the context is present the process is left with exactly the listed capabilities
+    pub fn from_module(md: &base::Module) -> Result<Self> {
+        let security = match &md.spec {
+            Some(base::ModuleSpec {
+                security: Some(security),
+                ..
+            }) => security,
+            _ => return Ok(Self::default()),
+        };
+
+        let user = if security.user.is_empty() {
+            None
+        } else {
+            Some(Self::resolve_user(&security.user)?)
+        };
+
+        // The group defaults to the primary group of the user
+        let gid = if security.group.is_empty() {
+            user.as_ref().map(|(_, gid)| *gid)
+        } else {
+            Some(Self::resolve_group(&security.group)?)
+        };
+
+        Ok(Self {
+            uid: user.map(|(uid, _)| uid),
+            gid,
+            capabilities: Some(Self::parse_capabilities(&security.capabilities)?),
+        })
+    }
+
+    /// parse_capabilities takes in capability names such as `CAP_BPF` or `bpf`
+    /// and returns the bitmask of the capabilities
+    pub fn parse_capabilities(names: &[String]) -> Result<u64> {
+        names.iter().try_fold(0u64, |mask, name| {
+            let name = name.trim().to_uppercase();
+            let name = if name.starts_with("CAP_") {
+                name
+            } else {
+                format!("CAP_{}", name)
+            };
+
+            CAPABILITIES
+                .iter()
+                .position(|cap| *cap == name)
+                .map(|cap| mask | 1 << cap)
+                .ok_or_else(|| anyhow!("unknown capability \"{}\"", name))
+        })
+    }
+
+    /// apply switches the calling process to the user, group and capabilities of
+    /// the security context and sets `no_new_privs`
+    ///
+    /// # Caveats
+    /// - apply is called in between fork and exec hence it must not allocate
+    pub fn apply(&self) -> io::Result<()> {
+        if *self == Self::default() {
+            return Ok(());
+        }
+
+        if let Some(caps) = self.capabilities {
+            // Drop the capabilities from the bounding set so that they can never be
+            // regained, capabilities unknown to the running kernel are skipped
+            for cap in 0..CAPABILITIES.len() {
+                if caps & 1 << cap == 0
+                    && unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0
+                    && io::Error::last_os_error().raw_os_error() != Some(libc::EINVAL)
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            // Keep the permitted capabilities across the switch of the user
+            Self::check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) })?;
+        }
+
+        if let Some(gid) = self.gid {
+            unistd::setgroups(&[gid]).map_err(Self::errno)?;
+            unistd::setgid(gid).map_err(Self::errno)?;
+        }
+
+        if let Some(uid) = self.uid {
+            unistd::setuid(uid).map_err(Self::errno)?;
+        }
+
+        if let Some(caps) = self.capabilities {
+            let header = CapHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let mut data = [CapData::default(); 2];
+            for (i, data) in data.iter_mut().enumerate() {
+                let set = (caps >> (32 * i)) as u32;
+                data.effective = set;
+                data.permitted = set;
+                data.inheritable = set;
+            }
+
+            Self::check(unsafe {
+                libc::syscall(libc::SYS_capset, &header, data.as_ptr()) as libc::c_int
+            })?;
+
+            // Ambient capabilities are what let an unprivileged user keep the
+            // capabilities after exec
+            for cap in (0..CAPABILITIES.len()).filter(|cap| caps & 1 << cap != 0) {
+                Self::check(unsafe {
+                    libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap, 0, 0)
+                })?;
+            }
+        }
+
+        Self::check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })
+    }
+
+    fn resolve_user(user: &str) -> Result<(Uid, Gid)> {
+        let found = match user.parse() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+            Err(_) => User::from_name(user)?,
+        };
+
+        match found {
+            Some(user) => Ok((user.uid, user.gid)),
+            // A numeric user does not need to exist in the user database, its
+            // group defaults to the one with the same id
+            None => match user.parse() {
+                Ok(uid) => Ok((Uid::from_raw(uid), Gid::from_raw(uid))),
+                Err(_) => Err(anyhow!("user \"{}\" not found", user)),
+            },
+        }
+    }
+
+    fn resolve_group(group: &str) -> Result<Gid> {
+        if let Ok(gid) = group.parse() {
+            return Ok(Gid::from_raw(gid));
+        }
+
+        match Group::from_name(group)? {
+            Some(group) => Ok(group.gid),
+            None => Err(anyhow!("group \"{}\" not found", group)),
+        }
+    }
+
+    fn check(res: libc::c_int) -> io::Result<()> {
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn errno(err: nix::Error) -> io::Error {
+        io::Error::from_raw_os_error(err as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_capabilities() {
+        let caps = Security::parse_capabilities(&[
+            "CAP_BPF".to_string(),
+            "perfmon".to_string(),
+            "CAP_NET_ADMIN".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(caps, 1 << 39 | 1 << 38 | 1 << 12);
+        assert_eq!(Security::parse_capabilities(&[]).unwrap(), 0);
+        assert!(Security::parse_capabilities(&["CAP_FLY".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::{anyhow, Result};
+use nix::unistd::{sysconf, SysconfVar};
+
+use super::limits::Cgroup;
+use crate::proto::base;
+
+/// Usage is a snapshot of the resources consumed by a process and all of
+/// its descendants
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// cpu_seconds is the user + system time consumed, including the time of
+    /// the children which have already been reaped
+    pub cpu_seconds: f64,
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+    pub threads: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl Usage {
+    /// sample takes in the pid of a process and reads the usage of the process
+    /// and its descendants from `/proc`, the processes and the CPU time are taken from
+    /// the cgroup of the process if there is one
+    pub fn sample(pid: u32, cgroup: Option<&Cgroup>) -> Result<Self> {
+        let ticks = match sysconf(SysconfVar::CLK_TCK) {
+            Ok(Some(ticks)) if ticks > 0 => ticks as f64,
+            _ => 100.0,
+        };
+
+        let mut usage = Self::default();
+        // The cgroup holds on to the descendants which left the process tree as well
+        let (pids, mut root) = match cgroup.map(Cgroup::procs) {
+            Some(Ok(pids)) => (pids, false),
+            _ => (Self::tree(pid), true),
+        };
+
+        for pid in pids {
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(stat) => Stat::parse(&stat)?,
+                // The root process must exist, descendants might have exited meanwhile
+                Err(err) if root => return Err(anyhow!("failed to read stat of {}: {}", pid, err)),
+                Err(_) => continue,
+            };
+
+            // The time of an exited descendant is accounted to the process which
+            // reaped it hence the time of every process includes its children
+            usage.cpu_seconds +=
+                (stat.utime + stat.stime + stat.cutime + stat.cstime) as f64 / ticks;
+            usage.threads += stat.threads;
+
+            if let Ok(status) = fs::read_to_string(format!("/proc/{}/status", pid)) {
+                usage.rss_bytes += Self::field(&status, "VmRSS:") * 1024;
+            }
+
+            if let Ok(io) = fs::read_to_string(format!("/proc/{}/io", pid)) {
+                usage.read_bytes += Self::field(&io, "read_bytes:");
+                usage.write_bytes += Self::field(&io, "write_bytes:");
+            }
+
+            if let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) {
+                usage.open_fds += fds.count() as u64;
+            }
+
+            root = false;
+        }
+
+        // Unlike the processes the cgroup also accounts for the descendants which were
+        // reaped by hyperion as orphans
+        if let Some(Ok(cpu_seconds)) = cgroup.map(Cgroup::cpu_seconds) {
+            usage.cpu_seconds = cpu_seconds;
+        }
+
+        Ok(usage)
+    }
+
+    /// tree returns the given pid followed by the pids of all of its descendants
+    fn tree(pid: u32) -> Vec<u32> {
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+
+        if let Ok(entries) = fs::read_dir("/proc") {
+            for entry in entries.flatten() {
+                let child = match entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+                    Some(child) => child,
+                    None => continue,
+                };
+
+                let stat = fs::read_to_string(entry.path().join("stat"))
+                    .ok()
+                    .and_then(|stat| Stat::parse(&stat).ok());
+                if let Some(stat) = stat {
+                    children.entry(stat.ppid).or_default().push(child);
+                }
+            }
+        }
+
+        let mut tree = vec![pid];
+        let mut i = 0;
+        while i < tree.len() {
+            if let Some(pids) = children.get(&tree[i]) {
+                tree.extend(pids);
+            }
+            i += 1;
+        }
+
+        tree
+    }
+
+    /// field returns the numeric value of the `key: value` line of the given file
+    fn field(content: &str, key: &str) -> u64 {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// add adds the usage of another process to the usage
+    pub fn add(&mut self, other: &Usage) {
+        self.cpu_seconds += other.cpu_seconds;
+        self.rss_bytes += other.rss_bytes;
+        self.open_fds += other.open_fds;
+        self.threads += other.threads;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+
+    /// to_proto converts the usage into its API representation
+    pub fn to_proto(&self) -> base::ResourceUsage {
+        base::ResourceUsage {
+            cpu_seconds: self.cpu_seconds,
+            rss_bytes: self.rss_bytes,
+            open_fds: self.open_fds,
+            threads: self.threads,
+            read_bytes: self.read_bytes,
+            write_bytes: self.write_bytes,
+        }
+    }
+}
+
+/// Stat holds the fields of `/proc/<pid>/stat` which are of interest
+pub(super) struct Stat {
+    pub state: char,
+    pub ppid: u32,
+    pub pgrp: u32,
+    pub utime: u64,
+    pub stime: u64,
+    pub cutime: u64,
+    pub cstime: u64,
+    pub threads: u64,
+    /// start_time is the time the process started after system boot in clock ticks,
+    /// together with the pid it uniquely identifies a process
+    pub start_time: u64,
+}
+
+impl Stat {
+    /// read returns the stat of the process with the given pid
+    pub fn read(pid: u32) -> Result<Self> {
+        Self::parse(&fs::read_to_string(format!("/proc/{}/stat", pid))?)
+    }
+
+    fn parse(stat: &str) -> Result<Self> {
+        // The process name can contain spaces and parentheses hence the fields
+        // are counted from the last closing parenthesis
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .map(|(_, rest)| rest.split_whitespace().collect())
+            .unwrap_or_default();
+
+        let field = |i: usize| -> Result<u64> {
+            fields
+                .get(i)
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| anyhow!("malformed process stat"))
+        };
+
+        Ok(Self {
+            state: fields
+                .first()
+                .and_then(|f| f.chars().next())
+                .ok_or_else(|| anyhow!("malformed process stat"))?,
+            ppid: field(1)? as u32,
+            pgrp: field(2)? as u32,
+            utime: field(11)?,
+            stime: field(12)?,
+            cutime: field(13)?,
+            cstime: field(14)?,
+            threads: field(17)?,
+            start_time: field(19)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let stat = Stat::parse(
+            "42 (my (odd) proc) S 7 42 42 0 -1 4194560 100 0 0 0 250 50 10 5 20 0 3 0 1000 0 0",
+        )
+        .unwrap();
+        assert_eq!(stat.ppid, 7);
+        assert_eq!(stat.pgrp, 42);
+        assert_eq!(stat.utime + stat.stime, 300);
+        assert_eq!(stat.cutime + stat.cstime, 15);
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.threads, 3);
+        assert_eq!(stat.start_time, 1000);
+
+        let usage = Usage::sample(std::process::id(), None).unwrap();
+        assert!(usage.rss_bytes > 0);
+        assert!(usage.threads > 0);
+        assert!(usage.open_fds > 0);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+
+use crate::proto::base;
+
+/// Secrets stores the secrets which modules refer to in their config, the values
+/// never leave hyperion other than through the processes of the modules
+#[derive(Clone, Default)]
+pub struct Secrets {
+    values: Arc<RwLock<HashMap<String, Vec<u8>>>>,
+}
+
+impl Secrets {
+    /// set stores the value of the secret, replacing the earlier one
+    pub fn set(&self, name: &str, value: Vec<u8>) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("secret name is required"));
+        }
+
+        self.values.write().unwrap().insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// delete removes the secret, deleting a secret which does not exist is a no-op
+    pub fn delete(&self, name: &str) {
+        self.values.write().unwrap().remove(name);
+    }
+
+    /// get returns the value of the secret
+    pub fn get(&self, name: &str) -> Result<Vec<u8>> {
+        self.values
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("secret \"{}\" not found", name))
+    }
+
+    /// names returns the names of the stored secrets in order
+    pub fn names(&self) -> Vec<String> {
+        let values = self.values.read().unwrap();
+        let mut names: Vec<String> = values.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// check returns an error if the secret values inlined in the config of the module
+    /// would replace a different value of a stored secret, secrets are shared by all of
+    /// the modules hence they are only replaced through the secrets API
+    pub fn check(&self, md: &base::Module) -> Result<()> {
+        let values = self.values.read().unwrap();
+
+        let mut inlined = BTreeMap::new();
+        for entry in Self::inlined(md) {
+            let value = inlined.entry(&entry.secret).or_insert(&entry.data);
+            if *value != &entry.data
+                || matches!(values.get(&entry.secret), Some(stored) if stored != &entry.data)
+            {
+                return Err(anyhow!(
+                    "secret \"{}\" already exists with a different value",
+                    entry.secret
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// extract stores the secret values which are inlined in the config of the module
+    /// and strips them from the definition, leaving only the references behind, so that
+    /// they do not show up when the module is listed
+    ///
+    /// The module must have passed `check` so that no stored value is replaced by a
+    /// different one, the inlined value is written regardless so that the module never
+    /// runs with a value other than the one it was applied with
+    pub fn extract(&self, md: &mut base::Module) {
+        let entries = match md.spec.as_mut() {
+            Some(spec) => &mut spec.config,
+            None => return,
+        };
+
+        let mut values = self.values.write().unwrap();
+        for entry in entries.iter_mut() {
+            if !entry.secret.is_empty() && !entry.data.is_empty() {
+                let value = std::mem::take(&mut entry.data);
+                values.insert(entry.secret.clone(), value);
+            }
+        }
+    }
+
+    fn inlined(md: &base::Module) -> impl Iterator<Item = &base::module_spec::ConfigEntry> {
+        md.spec
+            .iter()
+            .flat_map(|spec| spec.config.iter())
+            .filter(|entry| !entry.secret.is_empty() && !entry.data.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let secrets = Secrets::default();
+        secrets.set("token", b"new".to_vec()).unwrap();
+        secrets.set("other", b"old".to_vec()).unwrap();
+
+        let mut md = base::Module {
+            spec: Some(base::ModuleSpec {
+                config: vec![
+                    base::module_spec::ConfigEntry {
+                        name: "token".to_string(),
+                        data: b"new".to_vec(),
+                        secret: "token".to_string(),
+                        ..Default::default()
+                    },
+                    base::module_spec::ConfigEntry {
+                        name: "app.toml".to_string(),
+                        data: b"level = 1".to_vec(),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        secrets.check(&md).unwrap();
+        secrets.extract(&mut md);
+
+        let config = &md.spec.as_ref().unwrap().config;
+        assert!(config[0].data.is_empty());
+        assert_eq!(config[1].data, b"level = 1");
+        assert_eq!(secrets.get("token").unwrap(), b"new");
+        assert_eq!(
+            secrets.names(),
+            vec!["other".to_string(), "token".to_string()]
+        );
+
+        // A module cannot replace the value of a secret, re-applying it with a new value
+        // is rejected rather than the new value being dropped
+        let mut spec = md.spec.clone().unwrap();
+        spec.config[0].data = b"rotated".to_vec();
+        let rotated = base::Module {
+            spec: Some(spec.clone()),
+            ..Default::default()
+        };
+        assert!(secrets.check(&rotated).is_err());
+        assert_eq!(secrets.get("token").unwrap(), b"new");
+
+        spec.config[0].data = b"stolen".to_vec();
+        spec.config[0].secret = "other".to_string();
+        let md = base::Module {
+            spec: Some(spec),
+            ..Default::default()
+        };
+        assert!(secrets.check(&md).is_err());
+
+        secrets.delete("token");
+        assert!(secrets.get("token").is_err());
+    }
+}
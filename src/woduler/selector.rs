@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::proto::base;
+
+/// Requirement is a single condition that a set of labels must satisfy
+/// in order to be selected
+#[derive(Clone, Debug, PartialEq)]
+pub enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    DoesNotExist(String),
+}
+
+impl Requirement {
+    /// parse takes in a selector expression and returns the requirement
+    /// represented by it
+    ///
+    /// Supported expressions are:
+    /// - `key=value` or `key==value`
+    /// - `key!=value`
+    /// - `key in (v1,v2)`
+    /// - `key notin (v1,v2)`
+    /// - `key`
+    /// - `!key`
+    pub fn parse(expr: &str) -> Result<Self, error::SelectorParseErr> {
+        let expr = expr.trim();
+        let err = || error::SelectorParseErr(expr.to_string());
+
+        if expr.is_empty() {
+            return Err(err());
+        }
+
+        if let Some((key, values)) = expr.split_once(" notin ") {
+            return Ok(Self::NotIn(Self::parse_key(key)?, Self::parse_set(values)?));
+        }
+
+        if let Some((key, values)) = expr.split_once(" in ") {
+            return Ok(Self::In(Self::parse_key(key)?, Self::parse_set(values)?));
+        }
+
+        if let Some((key, value)) = expr.split_once("!=") {
+            return Ok(Self::NotEquals(
+                Self::parse_key(key)?,
+                Self::parse_value(value)?,
+            ));
+        }
+
+        if let Some((key, value)) = expr.split_once("==").or_else(|| expr.split_once('=')) {
+            return Ok(Self::Equals(
+                Self::parse_key(key)?,
+                Self::parse_value(value)?,
+            ));
+        }
+
+        if let Some(key) = expr.strip_prefix('!') {
+            return Ok(Self::DoesNotExist(Self::parse_key(key)?));
+        }
+
+        Ok(Self::Exists(Self::parse_key(expr)?))
+    }
+
+    /// matches returns true if the given labels satisfy the requirement
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Equals(k, v) => labels.get(k) == Some(v),
+            Self::NotEquals(k, v) => labels.get(k) != Some(v),
+            Self::In(k, vs) => matches!(labels.get(k), Some(v) if vs.contains(v)),
+            Self::NotIn(k, vs) => !matches!(labels.get(k), Some(v) if vs.contains(v)),
+            Self::Exists(k) => labels.contains_key(k),
+            Self::DoesNotExist(k) => !labels.contains_key(k),
+        }
+    }
+
+    fn parse_key(key: &str) -> Result<String, error::SelectorParseErr> {
+        let key = key.trim();
+
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || "!=(),".contains(c)) {
+            return Err(error::SelectorParseErr(key.to_string()));
+        }
+
+        Ok(key.to_string())
+    }
+
+    fn parse_value(value: &str) -> Result<String, error::SelectorParseErr> {
+        let value = value.trim();
+
+        if value.contains(|c: char| c.is_whitespace() || "!=(),".contains(c)) {
+            return Err(error::SelectorParseErr(value.to_string()));
+        }
+
+        Ok(value.to_string())
+    }
+
+    fn parse_set(values: &str) -> Result<Vec<String>, error::SelectorParseErr> {
+        let inner = values
+            .trim()
+            .strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .ok_or_else(|| error::SelectorParseErr(values.to_string()))?;
+
+        let set = inner
+            .split(',')
+            .map(Self::parse_value)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if set.iter().any(|v| v.is_empty()) {
+            return Err(error::SelectorParseErr(values.to_string()));
+        }
+
+        Ok(set)
+    }
+}
+
+/// Selector is a set of requirements which are ANDed together, an empty
+/// selector matches every set of labels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+impl Selector {
+    /// from_proto takes in a label selector and returns a selector made up of the
+    /// equality based `selector` map and the set based `expressions`
+    pub fn from_proto(ls: &base::LabelSelector) -> Result<Self, error::SelectorParseErr> {
+        let mut requirements: Vec<Requirement> = ls
+            .selector
+            .iter()
+            .map(|(k, v)| Requirement::Equals(k.clone(), v.clone()))
+            .collect();
+
+        for expr in ls.expressions.iter() {
+            requirements.push(Requirement::parse(expr)?);
+        }
+
+        Ok(Self { requirements })
+    }
+
+    /// matches returns true if the given labels satisfy all of the requirements
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+
+    /// matches_module returns true if the labels of the given module satisfy all
+    /// of the requirements, a module without metadata is treated as having no labels
+    pub fn matches_module(&self, md: &base::Module) -> bool {
+        match &md.metadata {
+            Some(metadata) => self.matches(&metadata.labels),
+            None => self.matches(&HashMap::new()),
+        }
+    }
+}
+
+pub mod error {
+    #[derive(Debug)]
+    pub struct SelectorParseErr(pub String);
+
+    impl std::fmt::Display for SelectorParseErr {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "invalid label selector expression: \"{}\"", self.0)
+        }
+    }
+
+    impl std::error::Error for SelectorParseErr {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Requirement::parse("env=prod").unwrap(),
+            Requirement::Equals("env".into(), "prod".into())
+        );
+        assert_eq!(
+            Requirement::parse("env == prod").unwrap(),
+            Requirement::Equals("env".into(), "prod".into())
+        );
+        assert_eq!(
+            Requirement::parse("env!=prod").unwrap(),
+            Requirement::NotEquals("env".into(), "prod".into())
+        );
+        assert_eq!(
+            Requirement::parse("env in (prod, dev)").unwrap(),
+            Requirement::In("env".into(), vec!["prod".into(), "dev".into()])
+        );
+        assert_eq!(
+            Requirement::parse("env notin (prod)").unwrap(),
+            Requirement::NotIn("env".into(), vec!["prod".into()])
+        );
+        assert_eq!(
+            Requirement::parse("env").unwrap(),
+            Requirement::Exists("env".into())
+        );
+        assert_eq!(
+            Requirement::parse("!env").unwrap(),
+            Requirement::DoesNotExist("env".into())
+        );
+
+        assert!(Requirement::parse("").is_err());
+        assert!(Requirement::parse("env in prod").is_err());
+        assert!(Requirement::parse("env in ()").is_err());
+        assert!(Requirement::parse("=prod").is_err());
+        assert!(Requirement::parse("env=pr od").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let selector = Selector::from_proto(&base::LabelSelector {
+            selector: labels(&[("app", "watcher")]),
+            expressions: vec![
+                "env in (prod,staging)".into(),
+                "tier!=frontend".into(),
+                "!debug".into(),
+            ],
+        })
+        .unwrap();
+
+        assert!(selector.matches(&labels(&[("app", "watcher"), ("env", "prod")])));
+        assert!(selector.matches(&labels(&[
+            ("app", "watcher"),
+            ("env", "staging"),
+            ("tier", "backend")
+        ])));
+        assert!(!selector.matches(&labels(&[("env", "prod")])));
+        assert!(!selector.matches(&labels(&[("app", "watcher"), ("env", "dev")])));
+        assert!(!selector.matches(&labels(&[
+            ("app", "watcher"),
+            ("env", "prod"),
+            ("tier", "frontend")
+        ])));
+        assert!(!selector.matches(&labels(&[
+            ("app", "watcher"),
+            ("env", "prod"),
+            ("debug", "true")
+        ])));
+
+        assert!(Selector::default().matches(&labels(&[])));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{collections::HashMap, env};
+
+use crate::proto::base;
+
+use super::dependency::Dependency;
+use super::event::{self, Distribution};
+use super::process::{Cgroup, Group, Limits, ModuleConfig, Sandbox, Schedule, Security};
+
+const SUPPORTED_SCHEMES: [&str; 3] = ["file://", "http://", "https://"];
+
+/// Violation is a field of a module definition which is not valid
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// field is the path to the field, e.g. `metadata.release.linux_amd64.sha256`
+    pub field: String,
+    pub description: String,
+}
+
+/// Violations is the error returned for a module definition which is not valid
+#[derive(Debug, Default)]
+pub struct Violations(pub Vec<Violation>);
+
+impl Violations {
+    fn add<D: std::fmt::Display>(&mut self, field: &str, description: D) {
+        self.0.push(Violation {
+            field: field.to_string(),
+            description: description.to_string(),
+        });
+    }
+
+    fn check<T, E: std::fmt::Display>(&mut self, field: &str, res: Result<T, E>) {
+        if let Err(err) = res {
+            self.add(field, err);
+        }
+    }
+}
+
+impl std::fmt::Display for Violations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid module - ")?;
+        for (i, violation) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", violation.field, violation.description)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Violations {}
+
+/// validate checks the module definition before it is applied and returns all of the
+/// fields which are not valid, so that the module is rejected instead of being retried
+/// by its process controller forever
+pub fn validate(md: &base::Module) -> Result<(), Violations> {
+    let mut violations = Violations::default();
+
+    match &md.core {
+        Some(core) if !is_name(&core.name) => violations.add(
+            "core.name",
+            format!(
+                "\"{}\" must consist of at most 63 lowercase alphanumeric characters or '-' and start and end with an alphanumeric character",
+                core.name
+            ),
+        ),
+        Some(_) => {}
+        None => violations.add("core", "is required"),
+    }
+
+    match &md.metadata {
+        Some(metadata) => {
+            validate_labels(&metadata.labels, &mut violations);
+            validate_releases(metadata.release.as_ref(), &mut violations);
+        }
+        None => violations.add("metadata", "is required"),
+    }
+
+    match event::Manager::input_selector(md) {
+        // The stdin of a process on a terminal is the terminal, data would be dropped
+        Ok(Some(_)) if md.spec.as_ref().map(|spec| spec.tty).unwrap_or_default() => {
+            violations.add("spec.tty", "cannot be combined with spec.data_source")
+        }
+        res => violations.check("spec.data_source.label", res),
+    }
+    violations.check("spec.security", Security::from_module(md));
+    violations.check("spec.sandbox", Sandbox::from_module(md));
+    violations.check("spec.schedule", Schedule::from_module(md));
+    violations.check("spec.depends_on", Dependency::from_module(md));
+    // The error names the init or sidecar process at fault
+    violations.check("spec", Group::from_module(md));
+    violations.check("spec.config", ModuleConfig::from_module(md));
+    // The limits are not silently dropped on a host without cgroups
+    let limits = Limits::from_module(md);
+    if !limits.is_empty() {
+        violations.check("spec.resources", limits.check(Cgroup::available()));
+    }
+    if let Some(spec) = &md.spec {
+        violations.check(
+            "spec.distribution",
+            spec.distribution.parse::<Distribution>(),
+        );
+    }
+
+    match violations.0.is_empty() {
+        true => Ok(()),
+        false => Err(violations),
+    }
+}
+
+fn validate_labels(labels: &HashMap<String, String>, violations: &mut Violations) {
+    // The order of the map is random, the violations are reported in a stable one
+    let mut labels: Vec<_> = labels.iter().collect();
+    labels.sort();
+
+    for (key, value) in labels {
+        let field = format!("metadata.labels[\"{}\"]", key);
+
+        let (prefix, name) = match key.split_once('/') {
+            Some((prefix, name)) => (Some(prefix), name),
+            None => (None, key.as_str()),
+        };
+        if !is_label_name(name) || !prefix.map(is_label_prefix).unwrap_or(true) {
+            violations.add(
+                &field,
+                "key must be an optional DNS subdomain prefix and '/' followed by at most 63 alphanumeric characters, '-', '_' or '.', starting and ending with an alphanumeric character",
+            );
+        }
+        if !value.is_empty() && !is_label_name(value) {
+            violations.add(
+                &field,
+                format!(
+                    "value \"{}\" must be at most 63 alphanumeric characters, '-', '_' or '.', starting and ending with an alphanumeric character",
+                    value
+                ),
+            );
+        }
+    }
+}
+
+fn validate_releases(
+    releases: Option<&base::module_metadata::Releases>,
+    violations: &mut Violations,
+) {
+    let releases = match releases {
+        Some(releases) => releases,
+        None => return violations.add("metadata.release", "is required"),
+    };
+
+    let all = [
+        ("metadata.release.linux_amd64", &releases.linux_amd64),
+        ("metadata.release.linux_arm64", &releases.linux_arm64),
+    ];
+    for (field, release) in all.iter() {
+        if let Some(release) = release {
+            validate_release(field, release, violations);
+        }
+    }
+
+    // The process can only be started if there is a release for this host
+    let host = match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => all[0],
+        ("linux", "aarch64") => all[1],
+        (os, arch) => {
+            return violations.add(
+                "metadata.release",
+                format!("OS \"{}\" arch \"{}\" is not supported", os, arch),
+            )
+        }
+    };
+    if host.1.is_none() {
+        violations.add(host.0, "is required for the host");
+    }
+}
+
+fn validate_release(
+    field: &str,
+    release: &base::module_metadata::releases::ModuleRelease,
+    violations: &mut Violations,
+) {
+    let location = release.location.as_str();
+    match SUPPORTED_SCHEMES
+        .iter()
+        .find_map(|scheme| location.strip_prefix(scheme))
+    {
+        Some(rest) if !rest.is_empty() => {}
+        Some(_) => violations.add(&format!("{}.location", field), "is required"),
+        None => violations.add(
+            &format!("{}.location", field),
+            format!(
+                "unsupported scheme in \"{}\" - expected one of \"file://\", \"http://\" or \"https://\"",
+                location
+            ),
+        ),
+    }
+
+    let field = format!("{}.sha256", field);
+    if release.sha256.is_empty() {
+        // The digest names the download directory of the binary
+        if location.starts_with("http") {
+            violations.add(&field, "is required for the releases which are downloaded");
+        }
+    } else if release.sha256.len() != 64 || !release.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        violations.add(&field, "must be 64 hexadecimal characters");
+    }
+}
+
+/// is_name returns true if the name is a lowercase DNS label
+fn is_name(name: &str) -> bool {
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn is_label_name(name: &str) -> bool {
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+fn is_label_prefix(prefix: &str) -> bool {
+    prefix.len() <= 253 && prefix.split('.').all(is_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(
+        location: &str,
+        sha256: &str,
+    ) -> Option<base::module_metadata::releases::ModuleRelease> {
+        Some(base::module_metadata::releases::ModuleRelease {
+            location: location.to_string(),
+            sha256: sha256.to_string(),
+        })
+    }
+
+    fn module(name: &str) -> base::Module {
+        base::Module {
+            core: Some(base::ModuleCore {
+                name: name.to_string(),
+            }),
+            metadata: Some(base::ModuleMetadata {
+                labels: [("core.hyperion.io/tier".to_string(), "edge".to_string())]
+                    .into_iter()
+                    .collect(),
+                release: Some(base::module_metadata::Releases {
+                    linux_amd64: release("file:///bin/cat", ""),
+                    linux_arm64: release("https://example.com/cat", &"a".repeat(64)),
+                }),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn fields(md: &base::Module) -> Vec<String> {
+        match validate(md) {
+            Ok(()) => Vec::new(),
+            Err(violations) => violations.0.into_iter().map(|v| v.field).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&module("cat-1")).is_ok());
+
+        let mut md = module("Cat_1");
+        let metadata = md.metadata.as_mut().unwrap();
+        metadata.labels.insert("-bad".to_string(), "ok".to_string());
+        metadata.release = Some(base::module_metadata::Releases {
+            linux_amd64: release("ftp://example.com/cat", "abc"),
+            linux_arm64: release("http://example.com/cat", ""),
+        });
+        md.spec = Some(base::ModuleSpec {
+            data_source: Some(base::module_spec::DataSource {
+                label: Some(base::LabelSelector {
+                    expressions: vec!["app in (a,".to_string()],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            distribution: "random".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            fields(&md),
+            vec![
+                "core.name",
+                "metadata.labels[\"-bad\"]",
+                "metadata.release.linux_amd64.location",
+                "metadata.release.linux_amd64.sha256",
+                "metadata.release.linux_arm64.sha256",
+                "spec.data_source.label",
+                "spec.distribution",
+            ]
+        );
+
+        assert_eq!(fields(&base::Module::default()), vec!["core", "metadata"]);
+
+        let mut md = module("cat");
+        md.spec = Some(base::ModuleSpec {
+            data_source: Some(base::module_spec::DataSource {
+                label: Some(base::LabelSelector {
+                    expressions: vec!["app in (a)".to_string()],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            tty: true,
+            ..Default::default()
+        });
+        assert_eq!(fields(&md), vec!["spec.tty"]);
+    }
+}